@@ -29,6 +29,11 @@ pub struct Workspace {
     pub id: i64,
     pub name: String,
     pub owner_id: i64,
+    pub description: String,
+    /// url of the icon, e.g. an uploaded file
+    pub icon: Option<String>,
+    /// public channels new members join
+    pub default_channels: Vec<i64>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -49,10 +54,44 @@ pub struct Chat {
     pub name: Option<String>,
     pub r#type: ChatType,
     pub members: Vec<i64>,
+    #[serde(alias = "ownerId")]
+    pub owner_id: i64,
     #[serde(alias = "createdAt")]
     pub created_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ChatRead {
+    #[serde(alias = "chatId")]
+    pub chat_id: i64,
+    #[serde(alias = "userId")]
+    pub user_id: i64,
+    #[serde(alias = "lastReadMessageId")]
+    pub last_read_message_id: i64,
+    #[serde(alias = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct UserPresence {
+    #[serde(alias = "userId")]
+    pub user_id: i64,
+    pub status: PresenceStatus,
+    #[serde(alias = "updatedAt")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "presence_status", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
+}
+
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq, PartialOrd, sqlx::Type)]
 #[sqlx(type_name = "chat_type", rename_all = "snake_case")]
 #[serde(rename_all(serialize = "camelCase"))]
@@ -77,6 +116,52 @@ pub struct Message {
     pub sender_id: i64,
     pub content: String,
     pub files: Vec<String>,
+    #[serde(alias = "replyTo")]
+    pub reply_to: Option<i64>,
+    #[serde(alias = "threadRootId")]
+    pub thread_root_id: Option<i64>,
+    #[serde(alias = "replyCount")]
+    pub reply_count: i32,
+    #[serde(alias = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(alias = "editedAt")]
+    pub edited_at: Option<DateTime<Utc>>,
+    #[serde(alias = "deletedAt")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub reactions: Vec<ReactionSummary>,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct MessageReaction {
+    #[serde(alias = "messageId")]
+    pub message_id: i64,
+    #[serde(alias = "chatId")]
+    pub chat_id: i64,
+    #[serde(alias = "userId")]
+    pub user_id: i64,
+    pub emoji: String,
+}
+
+/// Aggregated reactions of an emoji on a message
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ReactionSummary {
+    pub emoji: String,
+    pub count: i64,
+    /// whether the current user reacted with this emoji
+    pub reacted: bool,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct MessageRevision {
+    pub id: i64,
+    #[serde(alias = "messageId")]
+    pub message_id: i64,
+    pub content: String,
+    pub files: Vec<String>,
     #[serde(alias = "createdAt")]
     pub created_at: DateTime<Utc>,
 }
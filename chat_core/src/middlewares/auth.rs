@@ -44,9 +44,10 @@ where
         };
 
     let req = match state.verify(&token) {
-        Ok(user) => {
+        Ok((user, id)) => {
             let mut req = Request::from_parts(parts, body);
             req.extensions_mut().insert(user);
+            req.extensions_mut().insert(id);
             req
         }
         Err(e) => {
@@ -62,7 +63,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{DecodingKey, EncodingKey, User};
+    use crate::{DecodingKey, EncodingKey, TokenId, User};
     use anyhow::Result;
     use axum::{body::Body, middleware::from_fn_with_state, routing::get, Router};
     use std::sync::Arc;
@@ -79,8 +80,8 @@ mod tests {
     impl TokenVerify for AppState {
         type Error = ();
 
-        fn verify(&self, token: &str) -> Result<User, Self::Error> {
-            self.0.dk.verify(token).map_err(|_| ())
+        fn verify(&self, token: &str) -> Result<(User, TokenId), Self::Error> {
+            self.0.dk.verify_with_id(token).map_err(|_| ())
         }
     }
 
@@ -2,7 +2,7 @@ mod auth;
 mod request_id;
 mod server_time;
 
-use crate::User;
+use crate::{TokenId, User};
 use axum::{middleware::from_fn, Router};
 use std::fmt;
 use tower::ServiceBuilder;
@@ -19,7 +19,8 @@ pub use server_time::ServerTimeLayer;
 
 pub trait TokenVerify {
     type Error: fmt::Debug;
-    fn verify(&self, token: &str) -> Result<User, Self::Error>;
+    /// Verify the token, the user and the token id are added to the request extensions
+    fn verify(&self, token: &str) -> Result<(User, TokenId), Self::Error>;
 }
 
 pub const REQUEST_ID_HEADER: &str = "x-request-id";
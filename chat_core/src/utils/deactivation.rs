@@ -0,0 +1,76 @@
+use dashmap::DashSet;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashSet;
+
+/// channel of the notifications of workspace members deactivated or reactivated
+pub const MEMBER_CHANGED_CHANNEL: &str = "workspace_member_changed";
+
+/// A workspace member deactivated or reactivated
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MemberChanged {
+    pub ws_id: i64,
+    pub user_id: i64,
+    pub active: bool,
+}
+
+/// Deactivated workspace members as (ws_id, user_id), their tokens of the workspace are rejected.
+/// It's loaded once and kept up to date with the notifications of the member changes.
+#[derive(Debug, Default)]
+pub struct DeactivatedMembers {
+    members: DashSet<(i64, i64)>,
+}
+
+impl DeactivatedMembers {
+    pub fn is_deactivated(&self, ws_id: i64, user_id: i64) -> bool {
+        self.members.contains(&(ws_id, user_id))
+    }
+
+    pub fn apply(&self, changed: &MemberChanged) {
+        let key = (changed.ws_id, changed.user_id);
+        if changed.active {
+            self.members.remove(&key);
+        } else {
+            self.members.insert(key);
+        }
+    }
+
+    /// Load the deactivated members, replacing the ones loaded before
+    pub async fn load(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let members: Vec<(i64, i64)> = sqlx::query_as(
+            "SELECT ws_id, user_id FROM workspace_members WHERE deactivated_at IS NOT NULL",
+        )
+        .fetch_all(pool)
+        .await?;
+        // reactivated members are dropped, the others stay deactivated in between
+        let members: HashSet<_> = members.into_iter().collect();
+        self.members.retain(|key| members.contains(key));
+        for key in members {
+            self.members.insert(key);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deactivated_members_should_work() {
+        let deactivated = DeactivatedMembers::default();
+        let mut changed = MemberChanged {
+            ws_id: 1,
+            user_id: 2,
+            active: false,
+        };
+        deactivated.apply(&changed);
+        assert!(deactivated.is_deactivated(1, 2));
+        // only deactivated in that workspace
+        assert!(!deactivated.is_deactivated(2, 2));
+
+        changed.active = true;
+        deactivated.apply(&changed);
+        assert!(!deactivated.is_deactivated(1, 2));
+    }
+}
@@ -1,22 +1,45 @@
 use crate::User;
+use chrono::{DateTime, Utc};
 use jwt_simple::prelude::*;
 use std::ops::Deref;
+use uuid::Uuid;
 
-const JWT_DURATION: u64 = 60 * 60 * 24 * 7;
+/// access tokens are short lived, they're renewed with refresh tokens
+pub(crate) const JWT_DURATION: u64 = 60 * 15;
 const JWT_ISSUER: &str = "chat_server";
 const JWT_AUDIENCE: &str = "chat_web";
 
 pub struct EncodingKey(Ed25519KeyPair);
 pub struct DecodingKey(Ed25519PublicKey);
 
+/// Id of an access token, to revoke it before it expires
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenId {
+    pub jti: String,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
 impl EncodingKey {
     pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
         Ok(Self(Ed25519KeyPair::from_pem(pem)?))
     }
 
     pub fn sign(&self, user: impl Into<User>) -> Result<String, jwt_simple::Error> {
+        self.sign_with_id(user, Uuid::new_v4().simple().to_string())
+    }
+
+    /// Sign the token with the id given, the id is recorded before the token is issued
+    pub fn sign_with_id(
+        &self,
+        user: impl Into<User>,
+        jti: impl Into<String>,
+    ) -> Result<String, jwt_simple::Error> {
         let claims = Claims::with_custom_claims(user.into(), Duration::from_secs(JWT_DURATION));
-        let claims = claims.with_issuer(JWT_ISSUER).with_audience(JWT_AUDIENCE);
+        let claims = claims
+            .with_issuer(JWT_ISSUER)
+            .with_audience(JWT_AUDIENCE)
+            .with_jwt_id(jti.into());
         self.0.sign(claims)
     }
 }
@@ -26,18 +49,37 @@ impl DecodingKey {
         Ok(Self(Ed25519PublicKey::from_pem(pem)?))
     }
 
-    #[allow(unused)]
     pub fn verify(&self, token: &str) -> Result<User, jwt_simple::Error> {
-        let mut options = VerificationOptions {
+        Ok(self.verify_with_id(token)?.0)
+    }
+
+    /// Verify the token, along with its id to check if it's revoked
+    pub fn verify_with_id(&self, token: &str) -> Result<(User, TokenId), jwt_simple::Error> {
+        let options = VerificationOptions {
             allowed_issuers: Some(HashSet::from_strings(&[JWT_ISSUER])),
             allowed_audiences: Some(HashSet::from_strings(&[JWT_AUDIENCE])),
             ..Default::default()
         };
-        let claims = self.0.verify_token(token, Some(options))?;
-        Ok(claims.custom)
+        let claims = self.0.verify_token::<User>(token, Some(options))?;
+        // tokens issued without an id couldn't be revoked
+        let (Some(jti), Some(issued_at), Some(expires_at)) =
+            (claims.jwt_id, claims.issued_at, claims.expires_at)
+        else {
+            return Err(jwt_simple::Error::msg("token without id"));
+        };
+        let id = TokenId {
+            jti,
+            issued_at: to_datetime(issued_at),
+            expires_at: to_datetime(expires_at),
+        };
+        Ok((claims.custom, id))
     }
 }
 
+fn to_datetime(ts: UnixTimeStamp) -> DateTime<Utc> {
+    DateTime::from_timestamp(ts.as_secs() as _, 0).unwrap_or_default()
+}
+
 impl Deref for EncodingKey {
     type Target = Ed25519KeyPair;
 
@@ -76,6 +118,18 @@ mod tests {
 
         assert_eq!(user, ret);
 
+        let (ret, id) = dk.verify_with_id(&token)?;
+        assert_eq!(user, ret);
+        assert_eq!(id.jti.len(), 32);
+        assert_eq!(
+            (id.expires_at - id.issued_at).num_seconds(),
+            JWT_DURATION as i64
+        );
+        let (_, other) = dk.verify_with_id(&ek.sign(user.clone())?)?;
+        assert_ne!(id.jti, other.jti);
+        let (_, id) = dk.verify_with_id(&ek.sign_with_id(user, "session")?)?;
+        assert_eq!(id.jti, "session");
+
         Ok(())
     }
 }
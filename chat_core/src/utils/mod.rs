@@ -1,3 +1,9 @@
+mod deactivation;
 mod jwt;
+mod read;
+mod revocation;
 
-pub use jwt::{DecodingKey, EncodingKey};
+pub use deactivation::{DeactivatedMembers, MemberChanged, MEMBER_CHANGED_CHANNEL};
+pub use jwt::{DecodingKey, EncodingKey, TokenId};
+pub use read::mark_chat_read;
+pub use revocation::{TokenRevocations, TokenRevoked, TOKEN_REVOKED_CHANNEL};
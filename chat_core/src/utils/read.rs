@@ -0,0 +1,37 @@
+use crate::ChatRead;
+use sqlx::PgPool;
+
+/// Move the read position of the user in the chat forward to the message, it never goes
+/// backwards. None if the message isn't in the chat or the user isn't a member of it
+pub async fn mark_chat_read(
+    pool: &PgPool,
+    chat_id: i64,
+    user_id: i64,
+    message_id: i64,
+) -> Result<Option<ChatRead>, sqlx::Error> {
+    sqlx::query_as(
+        "
+        INSERT INTO chat_reads (chat_id, user_id, last_read_message_id)
+        SELECT m.chat_id, $2, m.id
+        FROM messages m
+        JOIN chats c ON c.id = m.chat_id
+        WHERE m.chat_id = $1 AND m.id = $3 AND $2 = ANY(c.members)
+        ON CONFLICT (chat_id, user_id) DO UPDATE
+        SET last_read_message_id = GREATEST(
+                chat_reads.last_read_message_id,
+                EXCLUDED.last_read_message_id
+            ),
+            updated_at = CASE
+                WHEN chat_reads.last_read_message_id < EXCLUDED.last_read_message_id
+                THEN NOW()
+                ELSE chat_reads.updated_at
+            END
+        RETURNING chat_id, user_id, last_read_message_id, updated_at
+        ",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .bind(message_id)
+    .fetch_optional(pool)
+    .await
+}
@@ -0,0 +1,163 @@
+use super::jwt::{TokenId, JWT_DURATION};
+use chrono::{DateTime, Duration, SubsecRound, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+/// channel of the notifications of revoked tokens, see revoked_tokens
+pub const TOKEN_REVOKED_CHANNEL: &str = "token_revoked";
+
+/// A token revoked by signing out, or all tokens of the user issued before a time
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TokenRevoked {
+    pub user_id: i64,
+    #[serde(default)]
+    pub jti: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub revoked_before: Option<DateTime<Utc>>,
+}
+
+/// Revoked tokens not expired yet, checked for each token verified.
+/// It's loaded once and kept up to date with the notifications of the revoked tokens.
+#[derive(Debug, Default)]
+pub struct TokenRevocations {
+    /// expiry of the revoked tokens, by jti
+    tokens: DashMap<String, DateTime<Utc>>,
+    /// tokens of the user issued before are revoked
+    users: DashMap<i64, DateTime<Utc>>,
+}
+
+impl TokenRevoked {
+    /// Whether the token of the user is revoked by this
+    pub fn revokes(&self, user_id: i64, token: &TokenId) -> bool {
+        self.user_id == user_id
+            && (self.jti.as_ref() == Some(&token.jti)
+                || self.revoked_before.is_some_and(|v| issued_before(token, v)))
+    }
+}
+
+/// Whether the token is issued before the time. issued_at is in whole seconds, so the tokens
+/// issued in the same second are revoked too, even if they're issued a bit later: a user signing
+/// in again in the second of signing out all sessions has to sign in once more
+fn issued_before(token: &TokenId, before: DateTime<Utc>) -> bool {
+    token.issued_at <= before.trunc_subsecs(0)
+}
+
+impl TokenRevocations {
+    pub fn is_revoked(&self, user_id: i64, token: &TokenId) -> bool {
+        self.tokens.contains_key(&token.jti)
+            || self
+                .users
+                .get(&user_id)
+                .is_some_and(|v| issued_before(token, *v))
+    }
+
+    pub fn apply(&self, revoked: &TokenRevoked) {
+        if let (Some(jti), Some(expires_at)) = (&revoked.jti, revoked.expires_at) {
+            self.prune();
+            self.tokens.insert(jti.clone(), expires_at);
+        }
+        if let Some(before) = revoked.revoked_before {
+            self.users.insert(revoked.user_id, before);
+        }
+    }
+
+    /// Load the revocations of the tokens not expired yet
+    pub async fn load(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let tokens: Vec<(String, DateTime<Utc>)> =
+            sqlx::query_as("SELECT jti, expires_at FROM revoked_tokens WHERE expires_at > NOW()")
+                .fetch_all(pool)
+                .await?;
+        let users: Vec<(i64, DateTime<Utc>)> = sqlx::query_as(
+            "
+            SELECT id, tokens_revoked_at FROM users
+            WHERE tokens_revoked_at > NOW() - $1::interval
+            ",
+        )
+        .bind(format!("{} seconds", JWT_DURATION))
+        .fetch_all(pool)
+        .await?;
+
+        // revocations only expire, the ones loaded before are kept until then
+        self.prune();
+        for (jti, expires_at) in tokens {
+            self.tokens.insert(jti, expires_at);
+        }
+        for (user_id, before) in users {
+            self.users.insert(user_id, before);
+        }
+        Ok(())
+    }
+
+    /// Forget the revocations of the expired tokens, they're rejected anyway
+    fn prune(&self) {
+        let now = Utc::now();
+        self.tokens.retain(|_, expires_at| *expires_at > now);
+        let issued_after = now - Duration::seconds(JWT_DURATION as _);
+        self.users.retain(|_, before| *before > issued_after);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_id(jti: &str, issued_at: DateTime<Utc>) -> TokenId {
+        TokenId {
+            jti: jti.to_string(),
+            issued_at,
+            expires_at: issued_at + Duration::seconds(JWT_DURATION as _),
+        }
+    }
+
+    #[test]
+    fn token_revocations_should_work() {
+        let revocations = TokenRevocations::default();
+        let now = Utc::now();
+        let old = token_id("old", now - Duration::seconds(10));
+        let new = token_id("new", now);
+        assert!(!revocations.is_revoked(1, &old));
+
+        let revoked = TokenRevoked {
+            user_id: 1,
+            jti: Some("old".to_string()),
+            expires_at: Some(old.expires_at),
+            revoked_before: None,
+        };
+        assert!(revoked.revokes(1, &old));
+        assert!(!revoked.revokes(1, &new));
+        revocations.apply(&revoked);
+        assert!(revocations.is_revoked(1, &old));
+        assert!(!revocations.is_revoked(1, &new));
+
+        let revoked = TokenRevoked {
+            user_id: 2,
+            jti: None,
+            expires_at: None,
+            revoked_before: Some(now - Duration::seconds(5)),
+        };
+        revocations.apply(&revoked);
+        let other = token_id("other", now - Duration::seconds(10));
+        assert!(revocations.is_revoked(2, &other));
+        assert!(!revocations.is_revoked(3, &other));
+        assert!(!revocations.is_revoked(2, &token_id("later", now)));
+
+        // tokens issued in the second of the revocation are revoked, whatever the sub-seconds
+        let second = now.trunc_subsecs(0);
+        let revoked = TokenRevoked {
+            user_id: 4,
+            jti: None,
+            expires_at: None,
+            revoked_before: Some(second + Duration::milliseconds(500)),
+        };
+        revocations.apply(&revoked);
+        let same = token_id("same", second);
+        assert!(revoked.revokes(4, &same));
+        assert!(revocations.is_revoked(4, &same));
+        let next = token_id("next", second + Duration::seconds(1));
+        assert!(!revoked.revokes(4, &next));
+        assert!(!revocations.is_revoked(4, &next));
+    }
+}
@@ -41,20 +41,29 @@ VALUES
         '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU'
     );
 
+-- all users are members of their workspace
+INSERT INTO
+    workspace_members(ws_id, user_id)
+SELECT
+    ws_id,
+    id
+FROM
+    users ON CONFLICT DO NOTHING;
+
 -- insert 4 chats
 -- insert public/private channel
 INSERT INTO
-    chats(ws_id, name, type, members)
+    chats(ws_id, name, type, members, owner_id)
 VALUES
-    (1, 'general', 'public_channel', '{1,2,3,4,5}'),
-    (1, 'private', 'private_channel', '{1,2,3}');
+    (1, 'general', 'public_channel', '{1,2,3,4,5}', 1),
+    (1, 'private', 'private_channel', '{1,2,3}', 1);
 
 -- insert unnamed chat
 INSERT INTO
-    chats(ws_id, type, members)
+    chats(ws_id, type, members, owner_id)
 VALUES
-    (1, 'single', '{1,2}'),
-    (1, 'group', '{1,3,4}');
+    (1, 'single', '{1,2}', 1),
+    (1, 'group', '{1,3,4}', 1);
 
 INSERT INTO
     messages(chat_id, sender_id, content)
@@ -69,3 +78,16 @@ VALUES
     (1, 3, 'How are you?'),
     (1, 1, 'Hello, world!'),
     (1, 1, 'Hello, world!');
+
+UPDATE
+    chats
+SET
+    last_message_id = 10
+WHERE
+    id = 1;
+
+-- insert a custom emoji
+INSERT INTO
+    custom_emojis(ws_id, shortcode, url, created_by)
+VALUES
+    (1, 'rust', '/files/1/rust.png', 1);
@@ -1,3 +1,4 @@
+use crate::mailer::MailerConfig;
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 use std::{env, fs::File, path::PathBuf};
@@ -6,6 +7,8 @@ use std::{env, fs::File, path::PathBuf};
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub mailer: MailerConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
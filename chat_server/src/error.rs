@@ -33,6 +33,12 @@ pub enum AppError {
     #[error("create chat error: {0}")]
     CreateChatError(String),
 
+    #[error("update chat error: {0}")]
+    UpdateChatError(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
     #[error("not found: {0}")]
     NotFound(String),
 
@@ -42,6 +48,27 @@ pub enum AppError {
     #[error("create message error: {0}")]
     CreateMessageError(String),
 
+    #[error("update message error: {0}")]
+    UpdateMessageError(String),
+
+    #[error("reaction error: {0}")]
+    ReactionError(String),
+
+    #[error("search error: {0}")]
+    SearchError(String),
+
+    #[error("workspace name already exists: {0}")]
+    WorkspaceNameAlreadyExists(String),
+
+    #[error("update workspace error: {0}")]
+    UpdateWorkspaceError(String),
+
+    #[error("invitation error: {0}")]
+    InvitationError(String),
+
+    #[error("refresh token error: {0}")]
+    RefreshTokenError(String),
+
     #[error("{0}")]
     ChatFileError(String),
 }
@@ -63,9 +90,18 @@ impl IntoResponse for AppError {
             Self::HttpHeaderParseError(_) => StatusCode::SERVICE_UNAVAILABLE,
             Self::EmailAlreadyExists(_) => StatusCode::CONFLICT,
             Self::CreateChatError(_) => StatusCode::BAD_REQUEST,
+            Self::UpdateChatError(_) => StatusCode::BAD_REQUEST,
+            Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
             Self::IoError(_) => StatusCode::SERVICE_UNAVAILABLE,
             Self::CreateMessageError(_) => StatusCode::BAD_REQUEST,
+            Self::UpdateMessageError(_) => StatusCode::BAD_REQUEST,
+            Self::ReactionError(_) => StatusCode::BAD_REQUEST,
+            Self::SearchError(_) => StatusCode::BAD_REQUEST,
+            Self::WorkspaceNameAlreadyExists(_) => StatusCode::CONFLICT,
+            Self::UpdateWorkspaceError(_) => StatusCode::BAD_REQUEST,
+            Self::InvitationError(_) => StatusCode::FORBIDDEN,
+            Self::RefreshTokenError(_) => StatusCode::UNAUTHORIZED,
             Self::ChatFileError(_) => StatusCode::BAD_REQUEST,
         };
 
@@ -1,14 +1,20 @@
 use crate::{
-    models::{CreateUser, SigninUser},
+    models::{CreateUser, RefreshToken, SigninUser, Signout},
     AppError, AppState, ErrorOutput,
 };
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use chat_core::{TokenId, User};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
 #[derive(Debug, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
 pub struct AuthOutput {
-    token: String,
+    /// short lived access token
+    pub(crate) token: String,
+    /// renews the access token with /api/token/refresh, it could only be used once
+    #[serde(alias = "refreshToken")]
+    pub(crate) refresh_token: String,
 }
 
 #[utoipa::path(
@@ -23,8 +29,7 @@ pub async fn signup_handler(
     Json(input): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let user = state.user_create(&input).await?;
-    let token = state.ek.sign(user)?;
-    let body = Json(AuthOutput { token });
+    let body = Json(auth_output(&state, user).await?);
     Ok((StatusCode::CREATED, body))
 }
 
@@ -42,8 +47,8 @@ pub async fn signin_handler(
     let user = state.user_verify(&input).await?;
     match user {
         Some(user) => {
-            let token = state.ek.sign(user)?;
-            Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
+            let body = Json(auth_output(&state, user).await?);
+            Ok((StatusCode::OK, body).into_response())
         }
         None => {
             let body = Json(ErrorOutput::new("Invalid email or password"));
@@ -52,10 +57,82 @@ pub async fn signin_handler(
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/token/refresh",
+    responses(
+        (status = 200, description = "Tokens renewed", body = AuthOutput),
+        (status = 401, description = "Invalid, expired or reused refresh token", body = ErrorOutput),
+    )
+)]
+pub async fn refresh_token_handler(
+    State(state): State<AppState>,
+    Json(input): Json<RefreshToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let (user, session) = state.token_refresh(&input).await?;
+    let token = state.ek.sign_with_id(user, session.access_jti)?;
+    Ok(Json(AuthOutput {
+        token,
+        refresh_token: session.refresh_token,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/signout",
+    responses(
+        (status = 204, description = "The token and the refresh tokens of the session revoked"),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn signout_handler(
+    Extension(user): Extension<User>,
+    Extension(id): Extension<TokenId>,
+    State(state): State<AppState>,
+    input: Option<Json<Signout>>,
+) -> Result<impl IntoResponse, AppError> {
+    let input = input.map(|Json(input)| input).unwrap_or_default();
+    state.signout(user.id as _, &id, &input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/signout/all",
+    responses(
+        (status = 204, description = "All tokens of the user revoked"),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn signout_all_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.signout_all(user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Tokens of a new sign in of the user
+pub(crate) async fn auth_output(state: &AppState, user: User) -> Result<AuthOutput, AppError> {
+    let session = state
+        .refresh_token_create(user.id as _, user.ws_id as _)
+        .await?;
+    let token = state.ek.sign_with_id(user, session.access_jti)?;
+    Ok(AuthOutput {
+        token,
+        refresh_token: session.refresh_token,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use anyhow::Result;
+    use chat_core::TokenVerify;
     use http_body_util::BodyExt;
 
     #[tokio::test]
@@ -78,13 +155,56 @@ mod tests {
         let email = "Meng@123.com";
         let password = "123456";
         let input = SigninUser::new(email, password);
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(State(state.clone()), Json(input))
             .await?
             .into_response();
         assert_eq!(ret.status(), StatusCode::OK);
         let body = ret.into_body().collect().await?.to_bytes();
         let ret: AuthOutput = serde_json::from_slice(&body)?;
         assert_ne!(ret.token, "");
+        assert_ne!(ret.refresh_token, "");
+
+        let input = RefreshToken {
+            refresh_token: ret.refresh_token.clone(),
+        };
+        let ret = refresh_token_handler(State(state.clone()), Json(input.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: AuthOutput = serde_json::from_slice(&body)?;
+        assert_eq!(state.dk.verify(&ret.token)?.id, 1);
+        assert_ne!(ret.refresh_token, input.refresh_token);
+
+        let ret = refresh_token_handler(State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signout_without_body_should_revoke_session() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = SigninUser::new("Meng@123.com", "123456");
+        let ret = signin_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: AuthOutput = serde_json::from_slice(&body)?;
+        let (user, id) = state.verify(&ret.token)?;
+
+        let res = signout_handler(Extension(user), Extension(id), State(state.clone()), None)
+            .await?
+            .into_response();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let input = RefreshToken {
+            refresh_token: ret.refresh_token,
+        };
+        let ret = refresh_token_handler(State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
         Ok(())
     }
 
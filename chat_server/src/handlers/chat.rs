@@ -1,6 +1,12 @@
-use crate::{models::CreateChat, AppError, AppState};
+use crate::{
+    models::{
+        ChatMember, CreateChat, ListChannels, ListChats, MarkRead, UpdateChat, UpdateChatMember,
+        UpdateMemberSettings,
+    },
+    AppError, AppState,
+};
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
     Extension, Json,
@@ -10,8 +16,11 @@ use chat_core::User;
 #[utoipa::path(
     get,
     path = "/api/chats",
+    params(
+        ListChats,
+    ),
     responses(
-        (status = 200, description = "List of chats", body = Vec<Chat>),
+        (status = 200, description = "List of chats", body = Vec<ChatSummary>),
     ),
     security(
         ("token" = [])
@@ -20,8 +29,11 @@ use chat_core::User;
 pub async fn list_chat_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
+    Query(input): Query<ListChats>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chats = state.fetch_chats(user.id as _, user.ws_id as _).await?;
+    let chats = state
+        .fetch_chats(input, user.id as _, user.ws_id as _)
+        .await?;
     Ok((StatusCode::OK, Json(chats)))
 }
 
@@ -46,6 +58,173 @@ pub async fn create_chat_handler(
     Ok((StatusCode::CREATED, Json(chat)))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/channels",
+    params(
+        ListChannels,
+    ),
+    responses(
+        (status = 200, description = "List of public channels", body = Vec<ChannelSummary>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn list_channel_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListChannels>,
+) -> Result<impl IntoResponse, AppError> {
+    let channels = state
+        .fetch_channels(input, user.id as _, user.ws_id as _)
+        .await?;
+    Ok((StatusCode::OK, Json(channels)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/join",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Joined the channel", body = Chat),
+        (status = 404, description = "Public channel not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn join_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.chat_join(id, user.id as _, user.ws_id as _).await?;
+    Ok((StatusCode::OK, Json(chat)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/leave",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 204, description = "Left the chat"),
+        (status = 400, description = "Single chat can't be left", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn leave_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.chat_leave(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/members",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "List of chat members", body = Vec<ChatMember>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn list_chat_members_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let members = state.list_chat_members(id).await?;
+    Ok(Json(members))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/members/{user_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("user_id" = u64, Path, description = "Member to update"),
+    ),
+    responses(
+        (status = 200, description = "Member updated", body = ChatMember),
+        (status = 400, description = "Chat owner can't be changed", body = ErrorOutput),
+        (status = 403, description = "Not allowed to manage roles", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn update_chat_member_handler(
+    Extension(member): Extension<ChatMember>,
+    State(state): State<AppState>,
+    Path((_id, user_id)): Path<(u64, u64)>,
+    Json(input): Json<UpdateChatMember>,
+) -> Result<impl IntoResponse, AppError> {
+    let member = state.chat_member_update(&member, user_id, input).await?;
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/settings",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Settings updated", body = ChatMember),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn update_chat_settings_handler(
+    Extension(member): Extension<ChatMember>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateMemberSettings>,
+) -> Result<impl IntoResponse, AppError> {
+    let member = state.chat_member_settings_update(&member, input).await?;
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/dm/{user_id}",
+    params(
+        ("user_id" = u64, Path, description = "User to chat with")
+    ),
+    responses(
+        (status = 200, description = "Single chat with the user, created if needed", body = Chat),
+        (status = 400, description = "Invalid user", body = ErrorOutput),
+        (status = 404, description = "User not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn open_single_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(other_id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state
+        .chat_open_single(user.id as _, other_id, user.ws_id as _)
+        .await?;
+    Ok((StatusCode::OK, Json(chat)))
+}
+
 #[utoipa::path(
     get,
     path = "/api/chats/{id}",
@@ -71,10 +250,97 @@ pub async fn get_chat_handler(
     }
 }
 
-pub async fn delete_chat_handler() -> impl IntoResponse {
-    "delete chat"
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 204, description = "Chat deleted"),
+        (status = 403, description = "Not allowed to delete the chat", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn delete_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.chat_delete(id, user.id as _, user.ws_id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Chat updated", body = Chat),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn update_chat_handler(
+    Extension(member): Extension<ChatMember>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<UpdateChat>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.chat_update(id, input, &member).await?;
+    Ok((StatusCode::OK, Json(chat)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/read",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Chat marked as read", body = ChatRead),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn mark_chat_read_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<MarkRead>,
+) -> Result<impl IntoResponse, AppError> {
+    let read = state.chat_mark_read(input, id, user.id as _).await?;
+    Ok((StatusCode::OK, Json(read)))
 }
 
-pub async fn update_chat_handler() -> impl IntoResponse {
-    "update chat"
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/typing",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 204, description = "Typing sent to the other members"),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn typing_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.chat_typing(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
 }
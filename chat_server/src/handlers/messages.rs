@@ -1,5 +1,8 @@
 use crate::{
-    models::{ChatFile, CreateMessage, ListMessage},
+    models::{
+        ChatFile, ChatMember, CreateMessage, ListMessage, ReactionInput, SearchMessage,
+        UpdateMessage,
+    },
     AppError, AppState,
 };
 use axum::{
@@ -39,14 +42,268 @@ pub async fn send_message_handler(
     )
 )]
 pub async fn list_message_handler(
+    Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(id): Path<i64>,
     Query(input): Query<ListMessage>,
 ) -> Result<impl IntoResponse, AppError> {
-    let msg = state.list_message(input, id as _).await?;
+    let msg = state.list_message(input, id as _, user.id as _).await?;
+    Ok(Json(msg))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{msg_id}/thread",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Thread root message id"),
+        ListMessage,
+    ),
+    responses(
+        (status = 200, description = "List of thread replies", body = Vec<Message>),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn list_thread_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(u64, u64)>,
+    Query(input): Query<ListMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let msg = state.list_thread(input, id, msg_id, user.id as _).await?;
     Ok(Json(msg))
 }
 
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/messages/{msg_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 200, description = "Message updated", body = Message),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Not the sender of the message", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn update_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(u64, u64)>,
+    Json(input): Json<UpdateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let msg = state
+        .message_update(input, id, msg_id, user.id as _)
+        .await?;
+    Ok(Json(msg))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{msg_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 204, description = "Message deleted"),
+        (status = 403, description = "Not allowed to delete the message", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn delete_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .message_delete(id, msg_id, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{msg_id}/revisions",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 200, description = "Prior revisions of the message", body = Vec<MessageRevision>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn list_message_revisions_handler(
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let revisions = state.list_message_revisions(id, msg_id).await?;
+    Ok(Json(revisions))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/messages/{msg_id}/reactions",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 201, description = "Reaction added", body = MessageReaction),
+        (status = 400, description = "Invalid emoji", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn add_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(u64, u64)>,
+    Json(input): Json<ReactionInput>,
+) -> Result<impl IntoResponse, AppError> {
+    let reaction = state.reaction_add(input, id, msg_id, user.id as _).await?;
+    Ok((StatusCode::CREATED, Json(reaction)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{msg_id}/reactions",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 204, description = "Reaction removed"),
+        (status = 404, description = "Reaction not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn remove_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(u64, u64)>,
+    Json(input): Json<ReactionInput>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .reaction_remove(input, id, msg_id, user.id as _)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/pins",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Pinned messages, the latest first", body = Vec<ChatPin>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn list_pins_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let pins = state.list_pins(id).await?;
+    Ok(Json(pins))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/messages/{msg_id}/pin",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 201, description = "Message pinned", body = ChatPin),
+        (status = 403, description = "Not allowed to pin messages", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn pin_message_handler(
+    Extension(member): Extension<ChatMember>,
+    State(state): State<AppState>,
+    Path((_id, msg_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let pin = state.message_pin(&member, msg_id).await?;
+    Ok((StatusCode::CREATED, Json(pin)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{msg_id}/pin",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 204, description = "Message unpinned"),
+        (status = 403, description = "Not allowed to pin messages", body = ErrorOutput),
+        (status = 404, description = "Pin not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn unpin_message_handler(
+    Extension(member): Extension<ChatMember>,
+    State(state): State<AppState>,
+    Path((_id, msg_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.message_unpin(&member, msg_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/search/messages",
+    params(
+        SearchMessage,
+    ),
+    responses(
+        (status = 200, description = "Matched messages", body = Vec<SearchResult>),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub async fn search_messages_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<SearchMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let results = state
+        .search_messages(input, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(results))
+}
+
 pub async fn file_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
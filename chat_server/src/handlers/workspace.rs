@@ -1,5 +1,17 @@
-use crate::{AppError, AppState};
-use axum::{extract::State, response::IntoResponse, Extension, Json};
+use crate::{
+    handlers::auth_output,
+    models::{
+        AcceptInvitation, CreateInvitation, TransferWorkspace, UpdateWorkspace,
+        UpdateWorkspaceMember,
+    },
+    AppError, AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
 use chat_core::User;
 
 #[utoipa::path(
@@ -19,3 +31,276 @@ pub(crate) async fn list_chat_users_handler(
     let users = state.fetch_all_chat_users(user.ws_id as _).await?;
     Ok(Json(users))
 }
+
+#[utoipa::path(
+    get,
+    path = "/api/users/presence",
+    responses(
+        (status = 200, description = "Presence of ws users", body = Vec<UserPresence>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_user_presence_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let presence = state.fetch_user_presence(user.ws_id as _).await?;
+    Ok(Json(presence))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/invitations",
+    responses(
+        (status = 200, description = "Pending invitations of the ws", body = Vec<Invitation>),
+        (status = 403, description = "Not an owner or admin of the ws", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_invitations_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let invitations = state.list_invitations(&user).await?;
+    Ok(Json(invitations))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/invitations",
+    responses(
+        (status = 201, description = "Invitation created", body = Invitation),
+        (status = 403, description = "Not an owner or admin of the ws", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_invitation_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateInvitation>,
+) -> Result<impl IntoResponse, AppError> {
+    let invitation = state.invitation_create(input, &user).await?;
+    Ok((StatusCode::CREATED, Json(invitation)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/invitations/{id}",
+    params(
+        ("id" = u64, Path, description = "Invitation id")
+    ),
+    responses(
+        (status = 204, description = "Invitation revoked"),
+        (status = 404, description = "Invitation not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn revoke_invitation_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.invitation_revoke(id, &user).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/invitations/accept",
+    responses(
+        (status = 200, description = "Joined the ws of the invitation", body = Workspace),
+        (status = 403, description = "Invalid invitation", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn accept_invitation_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<AcceptInvitation>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state.invitation_accept(input, &user).await?;
+    Ok(Json(ws))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspaces",
+    responses(
+        (status = 200, description = "Workspaces of the user", body = Vec<WorkspaceSummary>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_workspaces_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let workspaces = state.list_workspaces(user.id as _).await?;
+    Ok(Json(workspaces))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/{id}/switch",
+    params(
+        ("id" = u64, Path, description = "Workspace id")
+    ),
+    responses(
+        (status = 200, description = "Token for the workspace", body = AuthOutput),
+        (status = 404, description = "Workspace not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn switch_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.workspace_switch(id, &user).await?;
+    Ok(Json(auth_output(&state, user).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace",
+    responses(
+        (status = 200, description = "The active ws of the user", body = Workspace),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.find_workspace_by_id(user.ws_id as _).await? {
+        Some(ws) => Ok(Json(ws)),
+        None => Err(AppError::NotFound(format!("workspace id {}", user.ws_id))),
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/workspace",
+    responses(
+        (status = 200, description = "Workspace updated", body = Workspace),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Not an owner or admin of the ws", body = ErrorOutput),
+        (status = 409, description = "Workspace name already exists", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state.workspace_update(input, &user).await?;
+    Ok(Json(ws))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/transfer",
+    responses(
+        (status = 200, description = "Workspace transferred", body = Workspace),
+        (status = 403, description = "Not the owner of the ws", body = ErrorOutput),
+        (status = 404, description = "New owner is not a member of the ws", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn transfer_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TransferWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state.workspace_transfer(input, &user).await?;
+    Ok(Json(ws))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/members",
+    responses(
+        (status = 200, description = "Members of the ws, deactivated ones included", body = Vec<WorkspaceMember>),
+        (status = 403, description = "Not the owner or an admin of the ws", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_workspace_members_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let members = state.list_workspace_members(&user).await?;
+    Ok(Json(members))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/workspace/members/{user_id}",
+    params(
+        ("user_id" = u64, Path, description = "User id of the member")
+    ),
+    responses(
+        (status = 200, description = "Member updated", body = WorkspaceMember),
+        (status = 400, description = "Invalid role", body = ErrorOutput),
+        (status = 403, description = "Not allowed to manage the member", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_workspace_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(user_id): Path<u64>,
+    Json(input): Json<UpdateWorkspaceMember>,
+) -> Result<impl IntoResponse, AppError> {
+    let member = state.workspace_member_update(user_id, input, &user).await?;
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/workspace/members/{user_id}",
+    params(
+        ("user_id" = u64, Path, description = "User id of the member")
+    ),
+    responses(
+        (status = 204, description = "Member deactivated and removed from the chats of the ws"),
+        (status = 403, description = "Not allowed to manage the member", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn remove_workspace_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(user_id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.workspace_member_remove(user_id, &user).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
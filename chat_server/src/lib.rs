@@ -1,6 +1,8 @@
 mod config;
 mod error;
 mod handlers;
+mod listener;
+mod mailer;
 mod middlewares;
 mod models;
 mod openapi;
@@ -9,11 +11,15 @@ use anyhow::Context;
 use axum::{
     http::Method,
     middleware::from_fn_with_state,
-    routing::{get, post},
+    routing::{delete, get, patch, post},
     Router,
 };
-use chat_core::{set_layer, verify_token, DecodingKey, EncodingKey, TokenVerify, User};
+use chat_core::{
+    set_layer, verify_token, DeactivatedMembers, DecodingKey, EncodingKey, TokenId,
+    TokenRevocations, TokenVerify, User,
+};
 use handlers::*;
+use listener::setup_listener;
 use middlewares::verify_chat;
 use openapi::OpenApiRouter;
 use sqlx::PgPool;
@@ -23,6 +29,7 @@ use tower_http::cors::{self, CorsLayer};
 
 pub use config::AppConfig;
 pub use error::{AppError, ErrorOutput};
+pub use mailer::{FileMailer, LogMailer, Mail, Mailer, MailerConfig};
 pub use models::*;
 
 #[derive(Debug, Clone)]
@@ -36,6 +43,11 @@ pub struct AppStateInner {
     pub(crate) dk: DecodingKey,
     pub(crate) ek: EncodingKey,
     pub(crate) pool: PgPool,
+    pub(crate) mailer: Box<dyn Mailer>,
+    /// deactivated members, their tokens of the workspace are rejected
+    pub(crate) deactivated: DeactivatedMembers,
+    /// access tokens revoked by signing out
+    pub(crate) revocations: TokenRevocations,
 }
 
 pub async fn get_router(state: AppState) -> Result<Router, AppError> {
@@ -44,12 +56,38 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
             "/:id",
             get(get_chat_handler)
                 .patch(update_chat_handler)
-                .delete(delete_chat_handler)
                 .post(send_message_handler),
         )
+        .route("/:id/read", post(mark_chat_read_handler))
+        .route("/:id/typing", post(typing_handler))
+        .route("/:id/leave", post(leave_chat_handler))
+        .route("/:id/members", get(list_chat_members_handler))
+        .route("/:id/members/:user_id", patch(update_chat_member_handler))
+        .route("/:id/settings", patch(update_chat_settings_handler))
         .route("/:id/messages", get(list_message_handler))
+        .route("/:id/messages/:msg_id", patch(update_message_handler))
+        .route("/:id/messages/:msg_id/thread", get(list_thread_handler))
+        .route(
+            "/:id/messages/:msg_id/reactions",
+            post(add_reaction_handler).delete(remove_reaction_handler),
+        )
+        .route(
+            "/:id/messages/:msg_id/revisions",
+            get(list_message_revisions_handler),
+        )
+        .route(
+            "/:id/messages/:msg_id/pin",
+            post(pin_message_handler).delete(unpin_message_handler),
+        )
+        .route("/:id/pins", get(list_pins_handler))
         .layer(from_fn_with_state(state.clone(), verify_chat))
-        .route("/", get(list_chat_handler).post(create_chat_handler));
+        .route("/", get(list_chat_handler).post(create_chat_handler))
+        .route("/dm/:user_id", get(open_single_chat_handler))
+        // the workspace owner could delete chats it's not a member of
+        .route("/:id", delete(delete_chat_handler))
+        .route("/:id/messages/:msg_id", delete(delete_message_handler))
+        // non members could join public channels
+        .route("/:id/join", post(join_chat_handler));
 
     let cors = CorsLayer::new()
         // allow `GET` and `POST` when accessing the resource
@@ -64,14 +102,38 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
         .allow_headers(cors::Any);
 
     let api = Router::new()
+        .route("/channels", get(list_channel_handler))
         .route("/users", get(list_chat_users_handler))
+        .route("/users/presence", get(list_user_presence_handler))
+        .route(
+            "/invitations",
+            get(list_invitations_handler).post(create_invitation_handler),
+        )
+        .route("/invitations/accept", post(accept_invitation_handler))
+        .route("/invitations/:id", delete(revoke_invitation_handler))
+        .route(
+            "/workspace",
+            get(get_workspace_handler).patch(update_workspace_handler),
+        )
+        .route("/workspace/transfer", post(transfer_workspace_handler))
+        .route("/workspace/members", get(list_workspace_members_handler))
+        .route(
+            "/workspace/members/:user_id",
+            patch(update_workspace_member_handler).delete(remove_workspace_member_handler),
+        )
+        .route("/workspaces", get(list_workspaces_handler))
+        .route("/workspaces/:id/switch", post(switch_workspace_handler))
+        .route("/signout", post(signout_handler))
+        .route("/signout/all", post(signout_all_handler))
         .nest("/chats", chat)
+        .route("/search/messages", get(search_messages_handler))
         .route("/upload", post(upload_handler))
         .route("/files/:ws_id/*path", get(file_handler))
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
         // routes doesn't need token verification
         .route("/signin", post(signin_handler))
         .route("/signup", post(signup_handler))
+        .route("/token/refresh", post(refresh_token_handler))
         .layer(cors);
 
     let app = Router::new()
@@ -95,8 +157,21 @@ impl Deref for AppState {
 impl TokenVerify for AppState {
     type Error = AppError;
 
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
-        Ok(self.dk.verify(token)?)
+    fn verify(&self, token: &str) -> Result<(User, TokenId), Self::Error> {
+        let (user, id) = self.dk.verify_with_id(token)?;
+        if self.deactivated.is_deactivated(user.ws_id, user.id) {
+            return Err(AppError::PermissionDenied(format!(
+                "User {} is deactivated in workspace {}",
+                user.id, user.ws_id
+            )));
+        }
+        if self.revocations.is_revoked(user.id, &id) {
+            return Err(AppError::PermissionDenied(format!(
+                "Token of user {} has been revoked",
+                user.id
+            )));
+        }
+        Ok((user, id))
     }
 }
 
@@ -110,14 +185,22 @@ impl AppState {
         let pool = PgPool::connect(&config.server.db_url)
             .await
             .context("connect to db failed")?;
-        Ok(Self {
+        let mailer = config.mailer.build();
+        let state = Self {
             inner: Arc::new(AppStateInner {
                 config,
                 ek,
                 dk,
                 pool,
+                mailer,
+                deactivated: DeactivatedMembers::default(),
+                revocations: TokenRevocations::default(),
             }),
-        })
+        };
+        state.deactivated.load(&state.pool).await?;
+        state.revocations.load(&state.pool).await?;
+        setup_listener(state.clone());
+        Ok(state)
     }
 }
 
@@ -143,14 +226,20 @@ mod test_util {
             let post = config.server.db_url.rfind('/').expect("invalid db_url");
             let server_url = &config.server.db_url[..post];
             let (tdb, pool) = get_test_pool(Some(server_url)).await;
+            let mailer = config.mailer.build();
             let state = Self {
                 inner: Arc::new(AppStateInner {
                     config,
                     ek,
                     dk,
                     pool,
+                    mailer,
+                    deactivated: DeactivatedMembers::default(),
+                    revocations: TokenRevocations::default(),
                 }),
             };
+            state.deactivated.load(&state.pool).await?;
+            state.revocations.load(&state.pool).await?;
             Ok((tdb, state))
         }
     }
@@ -177,3 +266,62 @@ mod test_util {
         (tdb, pool)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    async fn delete(app: &Router, uri: &str, token: &str) -> Result<StatusCode> {
+        let req = Request::builder()
+            .method(Method::DELETE)
+            .uri(uri)
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        Ok(app.clone().oneshot(req).await?.status())
+    }
+
+    #[tokio::test]
+    async fn workspace_owner_should_delete_chat_without_membership() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 5).await?;
+        let owner = state.ek.sign(state.find_user_by_id(5).await?.unwrap())?;
+        let other = state.ek.sign(state.find_user_by_id(4).await?.unwrap())?;
+        let app = get_router(state).await?;
+
+        // neither user is a member of private channel 2
+        let status = delete(&app, "/api/chats/2", &other).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = delete(&app, "/api/chats/2", &owner).await?;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_owner_should_delete_message_without_membership() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 5).await?;
+        // chat 4 is a group of users 1, 3 and 4
+        let input = CreateMessage {
+            content: "hello group".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        let message = state.message_create(input, 4, 1).await?;
+        let owner = state.ek.sign(state.find_user_by_id(5).await?.unwrap())?;
+        let other = state.ek.sign(state.find_user_by_id(2).await?.unwrap())?;
+        let app = get_router(state).await?;
+
+        let uri = format!("/api/chats/4/messages/{}", message.id);
+        let status = delete(&app, &uri, &other).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = delete(&app, &uri, &owner).await?;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        Ok(())
+    }
+}
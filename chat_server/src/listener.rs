@@ -0,0 +1,56 @@
+use crate::AppState;
+use chat_core::{MemberChanged, TokenRevoked, MEMBER_CHANGED_CHANNEL, TOKEN_REVOKED_CHANNEL};
+use sqlx::postgres::{PgListener, PgNotification};
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+/// wait before listening again after the connection is lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Keep the deactivated members and the revoked tokens up to date with the changes made through
+/// other instances. They're loaded again after the connection is lost, changes may be missed
+/// meanwhile.
+pub(crate) fn setup_listener(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = listen(&state).await {
+                warn!("listener stopped: {}", e);
+            }
+            sleep(RECONNECT_DELAY).await;
+        }
+    });
+}
+
+async fn listen(state: &AppState) -> anyhow::Result<()> {
+    let mut listener = PgListener::connect_with(&state.pool).await?;
+    listener
+        .listen_all([MEMBER_CHANGED_CHANNEL, TOKEN_REVOKED_CHANNEL])
+        .await?;
+    state.deactivated.load(&state.pool).await?;
+    state.revocations.load(&state.pool).await?;
+    info!("listening to workspace member changes and revoked tokens");
+
+    // None once the connection is lost
+    while let Some(notif) = listener.try_recv().await? {
+        if let Err(e) = handle(state, &notif) {
+            warn!("invalid notification {:?}: {}", notif, e);
+        }
+    }
+    Ok(())
+}
+
+fn handle(state: &AppState, notif: &PgNotification) -> anyhow::Result<()> {
+    match notif.channel() {
+        MEMBER_CHANGED_CHANNEL => {
+            let changed: MemberChanged = serde_json::from_str(notif.payload())?;
+            state.deactivated.apply(&changed);
+        }
+        TOKEN_REVOKED_CHANNEL => {
+            let revoked: TokenRevoked = serde_json::from_str(notif.payload())?;
+            state.revocations.apply(&revoked);
+        }
+        _ => {}
+    }
+    Ok(())
+}
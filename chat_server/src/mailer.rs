@@ -0,0 +1,97 @@
+use crate::AppError;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::fs;
+use tracing::info;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Backend to deliver mails to users, e.g. workspace invitations
+#[async_trait]
+pub trait Mailer: Send + Sync + 'static {
+    async fn send(&self, mail: Mail) -> Result<(), AppError>;
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MailerConfig {
+    /// Log the mails, for development
+    #[default]
+    Log,
+    /// Write each mail into a file under the dir, for development and tests
+    File { dir: PathBuf },
+}
+
+pub struct LogMailer;
+
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl MailerConfig {
+    pub fn build(&self) -> Box<dyn Mailer> {
+        match self {
+            MailerConfig::Log => Box::new(LogMailer),
+            MailerConfig::File { dir } => Box::new(FileMailer::new(dir.clone())),
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: Mail) -> Result<(), AppError> {
+        info!("mail to {}: {}\n{}", mail.to, mail.subject, mail.body);
+        Ok(())
+    }
+}
+
+impl FileMailer {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> Result<(), AppError> {
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(format!("{}.eml", uuid::Uuid::now_v7()));
+        let content = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            mail.to, mail.subject, mail.body
+        );
+        fs::write(path, content).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn file_mailer_should_write_mail() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("mails-{}", uuid::Uuid::new_v4()));
+        let mailer = MailerConfig::File { dir: dir.clone() }.build();
+        let mail = Mail {
+            to: "alice@acme.org".to_string(),
+            subject: "hello".to_string(),
+            body: "world".to_string(),
+        };
+        mailer.send(mail).await?;
+
+        let mut entries = fs::read_dir(&dir).await?;
+        let entry = entries.next_entry().await?.expect("mail should exist");
+        let content = fs::read_to_string(entry.path()).await?;
+        assert_eq!(content, "To: alice@acme.org\nSubject: hello\n\nworld\n");
+        fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+}
@@ -5,24 +5,41 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use chat_core::User;
+use std::collections::HashMap;
 
 pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
-    let Path(chat_id) = Path::<u64>::from_request_parts(&mut parts, &state)
-        .await
-        .unwrap();
+    // chat routes may carry other ids (e.g. message id) besides the chat id
+    let Path(params) =
+        match Path::<HashMap<String, u64>>::from_request_parts(&mut parts, &state).await {
+            Ok(params) => params,
+            Err(e) => return e.into_response(),
+        };
+    let Some(&chat_id) = params.get("id") else {
+        return AppError::NotFound("chat id is missing".to_string()).into_response();
+    };
 
     let user = parts.extensions.get::<User>().unwrap();
-    if !state
-        .is_chat_member(chat_id, user.id as _)
-        .await
-        .unwrap_or_default()
-    {
-        let err = AppError::CreateMessageError(format!(
-            "User {} are not a member of chat {}",
-            chat_id, user.id
-        ));
-        return err.into_response();
+    // chats of other workspaces of the user are hidden until it switches to them
+    match state.get_chat_by_id(chat_id).await {
+        Ok(Some(chat)) if chat.ws_id == user.ws_id => {}
+        Ok(_) => {
+            return AppError::NotFound(format!("chat id {}", chat_id)).into_response();
+        }
+        Err(e) => return e.into_response(),
+    }
+    // handlers check what the member could do with its role
+    match state.get_chat_member(chat_id, user.id as _).await {
+        Ok(Some(member)) => {
+            parts.extensions.insert(member);
+        }
+        _ => {
+            let err = AppError::CreateMessageError(format!(
+                "User {} are not a member of chat {}",
+                chat_id, user.id
+            ));
+            return err.into_response();
+        }
     }
 
     let req = Request::from_parts(parts, body);
@@ -32,6 +49,7 @@ pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::{AcceptInvitation, CreateInvitation, CreateUser};
     use anyhow::Result;
     use axum::{
         body::Body, http::StatusCode, middleware::from_fn_with_state, routing::get, Router,
@@ -52,9 +70,10 @@ mod tests {
 
         let app = Router::new()
             .route("/chat/:id/messages", get(handler))
+            .route("/chat/:id/messages/:msg_id", get(handler))
             .layer(from_fn_with_state(state.clone(), verify_chat))
             .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
-            .with_state(state);
+            .with_state(state.clone());
 
         // user in chat
         let req = Request::builder()
@@ -64,14 +83,66 @@ mod tests {
         let res = app.clone().oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::OK);
 
-        // user not in chat
+        // user in chat with nested path
+        let req = Request::builder()
+            .uri("/chat/1/messages/1")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // chat not found
         let req = Request::builder()
             .uri("/chat/5/messages")
             .header("Authorization", format!("Bearer {}", token))
             .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // user not in chat
+        let other = state.find_user_by_id(5).await?.expect("user should exist");
+        let token = state.ek.sign(other)?;
+        let req = Request::builder()
+            .uri("/chat/2/messages")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
         let res = app.oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::BAD_REQUEST);
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn verify_chat_should_hide_chats_of_other_workspaces() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUser::new("zoo", "Zoo Meng", "zoo@123.com", "hunter42");
+        let owner = state.user_create(&input).await?;
+        let input = CreateInvitation {
+            email: Some("meng@123.com".to_string()),
+            ..Default::default()
+        };
+        let invitation = state.invitation_create(input, &owner).await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = AcceptInvitation {
+            token: invitation.token.unwrap(),
+        };
+        let ws = state.invitation_accept(input, &user).await?;
+        let user = state.workspace_switch(ws.id as _, &user).await?;
+        let token = state.ek.sign(user)?;
+
+        let app = Router::new()
+            .route("/chat/:id/messages", get(handler))
+            .layer(from_fn_with_state(state.clone(), verify_chat))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+            .with_state(state);
+
+        // still a member of chat 1 of acme, but the token is for zoo
+        let req = Request::builder()
+            .uri("/chat/1/messages")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
 }
@@ -1,7 +1,15 @@
-use crate::{AppError, AppState};
-use chat_core::{Chat, ChatType};
+use crate::{
+    models::{ChatCapability, ChatFile, ChatMember, WorkspaceRole},
+    AppError, AppState,
+};
+use chat_core::{mark_chat_read, Chat, ChatRead, ChatType, Message};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use sqlx::{FromRow, PgConnection};
+use std::{collections::HashMap, str::FromStr};
+use tokio::fs;
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
 
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize, Default)]
 pub struct CreateChat {
@@ -10,95 +18,606 @@ pub struct CreateChat {
     pub public: bool,
 }
 
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, Default)]
+pub struct UpdateChat {
+    /// New name of the chat
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Users to add to the chat
+    #[serde(default)]
+    pub add_members: Vec<i64>,
+    /// Users to remove from the chat
+    #[serde(default)]
+    pub remove_members: Vec<i64>,
+    /// Switch a named chat between public and private channel
+    #[serde(default)]
+    pub public: Option<bool>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, Default)]
+pub struct MarkRead {
+    /// Mark the chat read up to this message, or up to the latest message if not provided
+    #[serde(default)]
+    pub message_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize, Default)]
+pub struct ListChats {
+    /// Only list chats of this type
+    #[serde(default)]
+    pub r#type: Option<ChatType>,
+    /// Continue the list after this chat
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize, Default)]
+pub struct ListChannels {
+    /// Only list channels with a name containing this
+    #[serde(default)]
+    pub q: Option<String>,
+    /// Continue the list after this channel
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+/// A public channel of the workspace, the user may not be in it
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ChannelSummary {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub chat: Chat,
+    #[serde(alias = "memberCount")]
+    pub member_count: i64,
+    pub joined: bool,
+}
+
+/// A chat in the chat list of a user
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ChatSummary {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub chat: Chat,
+    #[serde(alias = "unreadCount")]
+    pub unread_count: i64,
+    #[serde(alias = "lastReadMessageId")]
+    pub last_read_message_id: Option<i64>,
+    #[serde(alias = "lastActivityAt")]
+    pub last_activity_at: DateTime<Utc>,
+    #[sqlx(skip)]
+    #[serde(default, alias = "lastMessage")]
+    pub last_message: Option<Message>,
+}
+
 #[allow(dead_code)]
 impl AppState {
+    /// Create a chat, a single chat between the same users is created only once and returned again
     pub async fn chat_create(
         &self,
-        input: CreateChat,
+        mut input: CreateChat,
         user_id: u64,
         ws_id: u64,
     ) -> Result<Chat, AppError> {
-        let len = input.members.len();
-        if len < 2 {
+        validate_chat(input.name.as_deref(), &input.members, user_id)
+            .map_err(AppError::CreateChatError)?;
+
+        let users = self.fetch_chat_user_by_ids(ws_id, &input.members).await?;
+        if users.len() != input.members.len() {
             return Err(AppError::CreateChatError(
-                "Chat must have at least 2 members".to_string(),
+                "Some members do not exist".to_string(),
             ));
         }
 
-        // if user id is not in members, reject
-        if !input.members.contains(&(user_id as i64)) {
+        let chat_type = get_chat_type(input.name.as_deref(), input.members.len(), input.public);
+        if chat_type == ChatType::Single {
+            input.members.sort();
+        }
+
+        // an existing single chat is left as is, see single_chat_members_index
+        let chat: Option<Chat> = sqlx::query_as(
+            "
+            INSERT INTO chats (ws_id, name, type, members, owner_id)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (ws_id, members) WHERE type = 'single' DO NOTHING
+            RETURNING id, ws_id, name, type, members, owner_id, created_at
+            ",
+        )
+        .bind(ws_id as i64)
+        .bind(input.name)
+        .bind(chat_type)
+        .bind(&input.members)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match chat {
+            Some(chat) => Ok(chat),
+            None => self
+                .find_single_chat(ws_id, &input.members)
+                .await?
+                .ok_or_else(|| AppError::CreateChatError("Failed to create chat".to_string())),
+        }
+    }
+
+    /// Open the single chat of the user with another user of the workspace, create it if needed
+    pub async fn chat_open_single(
+        &self,
+        user_id: u64,
+        other_id: u64,
+        ws_id: u64,
+    ) -> Result<Chat, AppError> {
+        if user_id == other_id {
             return Err(AppError::CreateChatError(
-                "You must be a member of the chat".to_string(),
+                "Can't open a chat with yourself".to_string(),
             ));
         }
+        if self.get_workspace_role(ws_id, other_id).await?.is_none() {
+            return Err(AppError::NotFound(format!("user id {}", other_id)));
+        }
 
-        if let Some(name) = &input.name {
-            if name.len() < 3 {
-                return Err(AppError::CreateChatError(
-                    "Chat name must have at least 3 characters".to_string(),
-                ));
+        let members = [user_id as i64, other_id as i64];
+        match self.find_single_chat(ws_id, &members).await? {
+            Some(chat) => Ok(chat),
+            None => {
+                let input = CreateChat {
+                    name: None,
+                    members: members.to_vec(),
+                    public: false,
+                };
+                self.chat_create(input, user_id, ws_id).await
             }
         }
+    }
 
-        if len > 8 && input.name.is_none() {
-            return Err(AppError::CreateChatError(
-                "Group chat with more than 8 must have a name".to_string(),
-            ));
+    /// Single chat between the users, in any order
+    pub async fn find_single_chat(
+        &self,
+        ws_id: u64,
+        members: &[i64],
+    ) -> Result<Option<Chat>, AppError> {
+        let mut members = members.to_vec();
+        members.sort();
+        let chat = sqlx::query_as(
+            "
+            SELECT id, ws_id, name, type, members, owner_id, created_at
+            FROM chats
+            WHERE ws_id = $1 AND type = 'single' AND members = $2
+            ",
+        )
+        .bind(ws_id as i64)
+        .bind(members)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(chat)
+    }
+
+    /// Update a chat, what could be changed depends on the role of the member in the chat
+    pub async fn chat_update(
+        &self,
+        id: u64,
+        input: UpdateChat,
+        member: &ChatMember,
+    ) -> Result<Chat, AppError> {
+        // members joining or leaving meanwhile wait for the update, and aren't overwritten by it
+        let mut tx = self.pool.begin().await?;
+        let Some(chat) = lock_chat(&mut tx, id).await? else {
+            return Err(AppError::NotFound(format!("chat id {}", id)));
+        };
+        let user_id = member.user_id as u64;
+
+        let renamed = input
+            .name
+            .as_ref()
+            .is_some_and(|v| chat.name.as_ref() != Some(v));
+        let toggled = input
+            .public
+            .is_some_and(|v| v != (chat.r#type == ChatType::PublicChannel));
+        if renamed || toggled {
+            member.require(ChatCapability::Rename)?;
         }
-        let users = self.fetch_chat_user_by_ids(&input.members).await?;
-        if users.len() != len {
-            return Err(AppError::CreateChatError(
+        if input.add_members.iter().any(|v| !chat.members.contains(v)) {
+            member.require(ChatCapability::Invite)?;
+        }
+        // members leave the chat with chat_leave, and the owner is never removed
+        let removed: Vec<_> = input
+            .remove_members
+            .iter()
+            .filter(|v| chat.members.contains(v))
+            .collect();
+        if !removed.is_empty() {
+            member.require(ChatCapability::Remove)?;
+            if removed.contains(&&chat.owner_id) {
+                return Err(AppError::PermissionDenied(format!(
+                    "User {} can't remove the owner of chat {}",
+                    user_id, id
+                )));
+            }
+        }
+
+        let name = input.name.or(chat.name);
+        let mut members = chat.members;
+        let mut added = vec![];
+        for member in input.add_members {
+            if !members.contains(&member) {
+                members.push(member);
+                added.push(member);
+            }
+        }
+        members.retain(|member| !input.remove_members.contains(member));
+
+        validate_chat(name.as_deref(), &members, user_id).map_err(AppError::UpdateChatError)?;
+
+        // deactivated members stay until removed, only the new ones must be active members
+        let users = self.fetch_chat_user_by_ids(chat.ws_id as _, &added).await?;
+        if users.len() != added.len() {
+            return Err(AppError::UpdateChatError(
                 "Some members do not exist".to_string(),
             ));
         }
 
-        let chat_type = match (&input.name, len) {
-            (None, 2) => ChatType::Single,
-            (None, _) => ChatType::Group,
-            (Some(_), _) => {
-                if input.public {
-                    ChatType::PublicChannel
-                } else {
-                    ChatType::PrivateChannel
-                }
+        let public = input
+            .public
+            .unwrap_or(chat.r#type == ChatType::PublicChannel);
+        let chat_type = get_chat_type(name.as_deref(), members.len(), public);
+        if chat_type == ChatType::Single {
+            members.sort();
+            let existing = self.find_single_chat(chat.ws_id as _, &members).await?;
+            if existing.is_some_and(|v| v.id != id as i64) {
+                return Err(AppError::UpdateChatError(
+                    "A single chat with these members already exists".to_string(),
+                ));
             }
-        };
+        }
 
         let chat = sqlx::query_as(
             "
-            INSERT INTO chats (ws_id, name, type, members)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, ws_id, name, type, members, created_at
+            UPDATE chats
+            SET name = $1, type = $2, members = $3
+            WHERE id = $4
+            RETURNING id, ws_id, name, type, members, owner_id, created_at
             ",
         )
-        .bind(ws_id as i64)
-        .bind(input.name)
+        .bind(name)
         .bind(chat_type)
-        .bind(input.members)
-        .fetch_one(&self.pool)
+        .bind(members)
+        .bind(id as i64)
+        .fetch_one(&mut *tx)
         .await?;
+        tx.commit().await?;
         Ok(chat)
     }
 
-    pub async fn fetch_chats(&self, user_id: u64, ws_id: u64) -> Result<Vec<Chat>, AppError> {
-        let chats = sqlx::query_as(
+    /// Delete a chat with all its messages, only the chat owner or the workspace owner could do it.
+    /// The workspace owner doesn't need to be a member of the chat, so it's not verified by verify_chat
+    pub async fn chat_delete(&self, id: u64, user_id: u64, ws_id: u64) -> Result<(), AppError> {
+        let Some(chat) = self.get_chat_by_id(id).await? else {
+            return Err(AppError::NotFound(format!("chat id {}", id)));
+        };
+        if chat.ws_id != ws_id as i64 {
+            return Err(AppError::NotFound(format!("chat id {}", id)));
+        }
+
+        let member = self.get_chat_member(id, user_id).await?;
+        if !member
+            .as_ref()
+            .is_some_and(|v| v.can(ChatCapability::Delete))
+        {
+            let ws = self.find_workspace_by_id(chat.ws_id as _).await?;
+            if ws.map(|ws| ws.owner_id) != Some(user_id as i64) {
+                let Some(member) = member else {
+                    return Err(AppError::PermissionDenied(format!(
+                        "User {} can't delete chat {}",
+                        user_id, id
+                    )));
+                };
+                member.require(ChatCapability::Delete)?;
+            }
+        }
+
+        let mut tx = self.pool.begin().await?;
+        // files referenced by this chat only, they could be released with the chat. Files of
+        // prior revisions count too, and emojis and workspace icons could use any uploaded file
+        let files: Vec<(String,)> = sqlx::query_as(
+            "
+            SELECT f FROM messages, unnest(files) AS f WHERE chat_id = $1
+            UNION
+            SELECT f FROM message_revisions r
+            JOIN messages m ON m.id = r.message_id, unnest(r.files) AS f
+            WHERE m.chat_id = $1
+            EXCEPT (
+                SELECT f FROM messages, unnest(files) AS f WHERE chat_id <> $1
+                UNION
+                SELECT f FROM message_revisions r
+                JOIN messages m ON m.id = r.message_id, unnest(r.files) AS f
+                WHERE m.chat_id <> $1
+                UNION
+                SELECT url FROM custom_emojis
+                UNION
+                SELECT icon FROM workspaces WHERE icon IS NOT NULL
+            )
+            ",
+        )
+        .bind(id as i64)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        // messages are deleted by the foreign key cascade
+        sqlx::query("DELETE FROM chats WHERE id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        let base_dir = &self.config.server.base_dir;
+        for (url,) in files {
+            let path = match ChatFile::from_str(&url) {
+                Ok(file) => file.path(base_dir),
+                Err(e) => {
+                    warn!("skip releasing file {}: {}", url, e);
+                    continue;
+                }
+            };
+            if let Err(e) = fs::remove_file(&path).await {
+                warn!("failed to remove file {:?}: {}", path, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// List chats of the user, most recently active first, with the last message of each chat
+    pub async fn fetch_chats(
+        &self,
+        input: ListChats,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<ChatSummary>, AppError> {
+        let limit = match input.limit {
+            0 => i64::MAX,
+            1..=100 => input.limit as _,
+            _ => 100,
+        };
+        let mut chats: Vec<ChatSummary> = sqlx::query_as(
             "
-            SELECT id, ws_id, name, type, members, created_at
-            FROM chats
-            WHERE ws_id = $1 AND $2 = ANY(members)
+            SELECT c.id, c.ws_id, c.name, c.type, c.members, c.owner_id, c.created_at,
+                c.last_activity_at, r.last_read_message_id,
+                (
+                    SELECT COUNT(*)
+                    FROM messages m
+                    WHERE m.chat_id = c.id
+                    AND m.id > COALESCE(r.last_read_message_id, 0)
+                    AND m.sender_id <> $2
+                    AND m.deleted_at IS NULL
+                ) AS unread_count
+            FROM chats c
+            LEFT JOIN chat_reads r ON r.chat_id = c.id AND r.user_id = $2
+            WHERE c.ws_id = $1 AND $2 = ANY(c.members)
+            AND ($3::chat_type IS NULL OR c.type = $3)
+            AND ($4::bigint IS NULL OR (c.last_activity_at, c.id) < (
+                SELECT last_activity_at, id FROM chats WHERE id = $4
+            ))
+            ORDER BY c.last_activity_at DESC, c.id DESC
+            LIMIT $5
             ",
         )
         .bind(ws_id as i64)
         .bind(user_id as i64)
+        .bind(input.r#type)
+        .bind(input.last_id.map(|v| v as i64))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let chat_ids: Vec<i64> = chats.iter().map(|c| c.chat.id).collect();
+        let messages: Vec<Message> = sqlx::query_as(
+            "
+            SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.reply_to, m.thread_root_id,
+                m.reply_count, m.created_at, m.edited_at, m.deleted_at
+            FROM chats c
+            JOIN messages m ON m.id = c.last_message_id
+            WHERE c.id = ANY($1)
+            ",
+        )
+        .bind(&chat_ids)
         .fetch_all(&self.pool)
         .await?;
+        let mut messages: HashMap<i64, Message> =
+            messages.into_iter().map(|m| (m.chat_id, m)).collect();
+        for chat in chats.iter_mut() {
+            chat.last_message = messages.remove(&chat.chat.id);
+        }
         Ok(chats)
     }
 
+    /// Public channels of the workspace by name, the user could join any of them
+    pub async fn fetch_channels(
+        &self,
+        input: ListChannels,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<ChannelSummary>, AppError> {
+        let limit = match input.limit {
+            0 => i64::MAX,
+            1..=100 => input.limit as _,
+            _ => 100,
+        };
+        // wildcards typed by the user are matched literally
+        let q = input
+            .q
+            .map(|q| {
+                q.trim()
+                    .replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            })
+            .filter(|q| !q.is_empty());
+        // guests only see the channels they're added to
+        let guest = self.get_workspace_role(ws_id, user_id).await? == Some(WorkspaceRole::Guest);
+        let channels = sqlx::query_as(
+            "
+            SELECT id, ws_id, name, type, members, owner_id, created_at,
+                cardinality(members)::bigint AS member_count,
+                $2 = ANY(members) AS joined
+            FROM chats
+            WHERE ws_id = $1 AND type = 'public_channel'
+            AND ($3::text IS NULL OR name ILIKE '%' || $3 || '%' ESCAPE '\\')
+            AND id > $4
+            AND (NOT $6 OR $2 = ANY(members))
+            ORDER BY id
+            LIMIT $5
+            ",
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(q)
+        .bind(input.last_id.unwrap_or(0) as i64)
+        .bind(limit)
+        .bind(guest)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(channels)
+    }
+
+    /// Join a public channel of the workspace, joining again does nothing
+    pub async fn chat_join(&self, id: u64, user_id: u64, ws_id: u64) -> Result<Chat, AppError> {
+        let Some(chat) = self.get_chat_by_id(id).await? else {
+            return Err(AppError::NotFound(format!("chat id {}", id)));
+        };
+        // private chats are hidden to non members
+        if chat.ws_id != ws_id as i64 || chat.r#type != ChatType::PublicChannel {
+            return Err(AppError::NotFound(format!("chat id {}", id)));
+        }
+        if chat.members.contains(&(user_id as i64)) {
+            return Ok(chat);
+        }
+        if self.get_workspace_role(ws_id, user_id).await? == Some(WorkspaceRole::Guest) {
+            return Err(AppError::PermissionDenied(format!(
+                "Guest {} can't join channels of workspace {}",
+                user_id, ws_id
+            )));
+        }
+
+        let chat = sqlx::query_as(
+            "
+            UPDATE chats
+            SET members = array_append(members, $2)
+            WHERE id = $1 AND NOT $2 = ANY(members)
+            RETURNING id, ws_id, name, type, members, owner_id, created_at
+            ",
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        match chat {
+            Some(chat) => Ok(chat),
+            // joined by a concurrent request
+            None => self
+                .get_chat_by_id(id)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("chat id {}", id))),
+        }
+    }
+
+    /// Leave a channel or a group, a single chat can't be left.
+    /// The owner stays in the chat, and so does the last member, the chat should be deleted instead
+    pub async fn chat_leave(&self, id: u64, user_id: u64) -> Result<(), AppError> {
+        let Some(chat) = self.get_chat_by_id(id).await? else {
+            return Err(AppError::NotFound(format!("chat id {}", id)));
+        };
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::UpdateChatError(
+                "Can't leave a single chat".to_string(),
+            ));
+        }
+        if chat.owner_id == user_id as i64 {
+            return Err(AppError::UpdateChatError(format!(
+                "The owner can't leave chat {}",
+                id
+            )));
+        }
+        if chat.members == [user_id as i64] {
+            return Err(AppError::UpdateChatError(format!(
+                "The last member can't leave chat {}",
+                id
+            )));
+        }
+
+        // checked again in the update, as members could leave concurrently
+        let ret = sqlx::query(
+            "
+            UPDATE chats
+            SET members = array_remove(members, $2)
+            WHERE id = $1 AND $2 = ANY(members) AND owner_id <> $2 AND cardinality(members) > 1
+            ",
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 && chat.members.contains(&(user_id as i64)) {
+            return Err(AppError::UpdateChatError(format!(
+                "The last member can't leave chat {}",
+                id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Move the read position of the user in the chat forward, it never goes backwards
+    pub async fn chat_mark_read(
+        &self,
+        input: MarkRead,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ChatRead, AppError> {
+        let message_id = match input.message_id {
+            Some(id) => Some(id as i64),
+            None => {
+                let latest: Option<(i64,)> = sqlx::query_as(
+                    "SELECT id FROM messages WHERE chat_id = $1 ORDER BY id DESC LIMIT 1",
+                )
+                .bind(chat_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+                latest.map(|(id,)| id)
+            }
+        };
+        // the same as acking the message over the websocket of notify server
+        let read = match message_id {
+            Some(id) => mark_chat_read(&self.pool, chat_id as _, user_id as _, id).await?,
+            None => None,
+        };
+        read.ok_or_else(|| AppError::NotFound(format!("message in chat id {}", chat_id)))
+    }
+
+    /// Tell the other members that the user is typing, nothing is stored
+    pub async fn chat_typing(&self, chat_id: u64, user_id: u64) -> Result<(), AppError> {
+        sqlx::query(
+            "
+            SELECT pg_notify('chat_typing', json_build_object(
+                'typing', json_build_object('chat_id', id, 'user_id', $2::bigint)
+            )::text)
+            FROM chats
+            WHERE id = $1
+            ",
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
     pub async fn get_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
         let chat = sqlx::query_as(
             "
-            SELECT id, ws_id, name, type, members, created_at
+            SELECT id, ws_id, name, type, members, owner_id, created_at
             FROM chats
             WHERE id = $1
             ",
@@ -126,6 +645,60 @@ impl AppState {
     }
 }
 
+/// Find the chat by id and lock it until the end of the transaction
+async fn lock_chat(conn: &mut PgConnection, id: u64) -> Result<Option<Chat>, AppError> {
+    let chat = sqlx::query_as(
+        "
+        SELECT id, ws_id, name, type, members, owner_id, created_at
+        FROM chats
+        WHERE id = $1
+        FOR UPDATE
+        ",
+    )
+    .bind(id as i64)
+    .fetch_optional(conn)
+    .await?;
+    Ok(chat)
+}
+
+fn validate_chat(name: Option<&str>, members: &[i64], user_id: u64) -> Result<(), String> {
+    let len = members.len();
+    if len < 2 {
+        return Err("Chat must have at least 2 members".to_string());
+    }
+
+    // if user id is not in members, reject
+    if !members.contains(&(user_id as i64)) {
+        return Err("You must be a member of the chat".to_string());
+    }
+
+    if let Some(name) = name {
+        if name.len() < 3 {
+            return Err("Chat name must have at least 3 characters".to_string());
+        }
+    }
+
+    if len > 8 && name.is_none() {
+        return Err("Group chat with more than 8 must have a name".to_string());
+    }
+
+    Ok(())
+}
+
+fn get_chat_type(name: Option<&str>, len: usize, public: bool) -> ChatType {
+    match (name, len) {
+        (None, 2) => ChatType::Single,
+        (None, _) => ChatType::Group,
+        (Some(_), _) => {
+            if public {
+                ChatType::PublicChannel
+            } else {
+                ChatType::PrivateChannel
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 impl CreateChat {
     pub fn new(name: &str, members: &[i64], public: bool) -> Self {
@@ -145,12 +718,14 @@ impl CreateChat {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::{ChatRole, CreateMessage, UpdateChatMember, UpdateMessage};
     use anyhow::Result;
+    use sqlx::postgres::PgListener;
 
     #[tokio::test]
     async fn create_single_chat_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let input = CreateChat::new("", &[1, 2], false);
+        let input = CreateChat::new("", &[1, 5], false);
         let chat = state
             .chat_create(input, 1, 1)
             .await
@@ -161,6 +736,46 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn create_single_chat_twice_should_return_existing() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 3 is the single chat of user 1 and 2
+        let input = CreateChat::new("", &[2, 1], false);
+        let chat = state.chat_create(input, 2, 1).await?;
+        assert_eq!(chat.id, 3);
+        assert_eq!(chat.members, vec![1, 2]);
+
+        let input = CreateChat::new("", &[5, 1], false);
+        let chat = state.chat_create(input, 5, 1).await?;
+        assert_eq!(chat.members, vec![1, 5]);
+        let input = CreateChat::new("", &[1, 5], false);
+        let chat2 = state.chat_create(input, 1, 1).await?;
+        assert_eq!(chat2.id, chat.id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_open_single_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chat = state.chat_open_single(2, 1, 1).await?;
+        assert_eq!(chat.id, 3);
+
+        let chat = state.chat_open_single(3, 4, 1).await?;
+        assert_eq!(chat.r#type, ChatType::Single);
+        assert_eq!(chat.members, vec![3, 4]);
+        let chat2 = state.chat_open_single(4, 3, 1).await?;
+        assert_eq!(chat2.id, chat.id);
+
+        let err = state.chat_open_single(1, 1, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "create chat error: Can't open a chat with yourself"
+        );
+        let err = state.chat_open_single(1, 100, 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "not found: user id 100");
+        Ok(())
+    }
+
     #[tokio::test]
     async fn create_public_named_chat_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -175,6 +790,231 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn update_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.get_chat_member(4, 1).await?.unwrap();
+        // chat 4 is an unnamed group with members 1, 3, 4
+        let input = UpdateChat {
+            name: Some("project".to_string()),
+            add_members: vec![2],
+            remove_members: vec![4],
+            public: None,
+        };
+        let chat = state.chat_update(4, input, &owner).await?;
+        assert_eq!(chat.name.as_deref(), Some("project"));
+        assert_eq!(chat.members, vec![1, 3, 2]);
+        assert_eq!(chat.r#type, ChatType::PrivateChannel);
+
+        let input = UpdateChat {
+            public: Some(true),
+            ..Default::default()
+        };
+        let chat = state.chat_update(4, input, &owner).await?;
+        assert_eq!(chat.name.as_deref(), Some("project"));
+        assert_eq!(chat.r#type, ChatType::PublicChannel);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_chat_should_keep_concurrent_joins() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.get_chat_member(4, 1).await?.unwrap();
+        let input = UpdateChat {
+            name: Some("project".to_string()),
+            public: Some(true),
+            ..Default::default()
+        };
+        state.chat_update(4, input, &owner).await?;
+
+        let input = UpdateChat {
+            add_members: vec![2],
+            ..Default::default()
+        };
+        let (updated, joined) = tokio::join!(
+            state.chat_update(4, input, &owner),
+            state.chat_join(4, 5, 1)
+        );
+        updated?;
+        joined?;
+        let chat = state.get_chat_by_id(4).await?.unwrap();
+        assert!(chat.members.contains(&2));
+        assert!(chat.members.contains(&5));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_chat_should_check_member_role() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // user 3 is a plain member of chat 4
+        let member = state.get_chat_member(4, 3).await?.unwrap();
+        let input = UpdateChat {
+            name: Some("project".to_string()),
+            ..Default::default()
+        };
+        let err = state.chat_update(4, input, &member).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 3 can't rename chat 4"
+        );
+
+        let input = UpdateChat {
+            remove_members: vec![4],
+            ..Default::default()
+        };
+        let err = state.chat_update(4, input, &member).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 3 can't remove members from chat 4"
+        );
+
+        // members could invite others
+        let input = UpdateChat {
+            add_members: vec![2],
+            ..Default::default()
+        };
+        let chat = state.chat_update(4, input, &member).await?;
+        assert_eq!(chat.members, vec![1, 3, 4, 2]);
+
+        // admins can't remove the owner
+        let owner = state.get_chat_member(4, 1).await?.unwrap();
+        let input = UpdateChatMember {
+            role: ChatRole::Admin,
+        };
+        let admin = state.chat_member_update(&owner, 3, input).await?;
+        let input = UpdateChat {
+            remove_members: vec![1],
+            ..Default::default()
+        };
+        let err = state.chat_update(4, input, &admin).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 3 can't remove the owner of chat 4"
+        );
+
+        let input = UpdateChat {
+            remove_members: vec![4],
+            ..Default::default()
+        };
+        let chat = state.chat_update(4, input, &admin).await?;
+        assert_eq!(chat.members, vec![1, 3, 2]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_chat_with_invalid_input_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.get_chat_member(3, 1).await?.unwrap();
+        // single chat 3 can't drop below 2 members
+        let input = UpdateChat {
+            remove_members: vec![2],
+            ..Default::default()
+        };
+        let err = state.chat_update(3, input, &owner).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "update chat error: Chat must have at least 2 members"
+        );
+
+        let input = UpdateChat {
+            add_members: vec![100],
+            ..Default::default()
+        };
+        let err = state.chat_update(3, input, &owner).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "update chat error: Some members do not exist"
+        );
+
+        // group chat 4 would become another single chat of user 1 and 2
+        let owner = state.get_chat_member(4, 1).await?.unwrap();
+        let input = UpdateChat {
+            add_members: vec![2],
+            remove_members: vec![3, 4],
+            ..Default::default()
+        };
+        let err = state.chat_update(4, input, &owner).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "update chat error: A single chat with these members already exists"
+        );
+
+        let input = UpdateChat::default();
+        let err = state.chat_update(100, input, &owner).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = ChatFile::new(1, "delete.txt", b"delete me");
+        let path = file.path(&state.config.server.base_dir);
+        std::fs::create_dir_all(path.parent().expect("file path parent should exists"))?;
+        std::fs::write(&path, b"delete me")?;
+        let input = CreateMessage {
+            content: "file".to_string(),
+            files: vec![file.url()],
+            reply_to: None,
+        };
+        let message = state.message_create(input, 1, 1).await?;
+        // the file is only left in the revision
+        let input = UpdateMessage {
+            content: "no file".to_string(),
+            files: Some(vec![]),
+        };
+        state.message_update(input, 1, message.id as _, 1).await?;
+
+        // the icon of the workspace is uploaded to the chat too
+        let icon = ChatFile::new(1, "icon.png", b"icon");
+        let icon_path = icon.path(&state.config.server.base_dir);
+        std::fs::create_dir_all(icon_path.parent().expect("file path parent should exists"))?;
+        std::fs::write(&icon_path, b"icon")?;
+        let input = CreateMessage {
+            content: "icon".to_string(),
+            files: vec![icon.url()],
+            reply_to: None,
+        };
+        state.message_create(input, 1, 1).await?;
+        sqlx::query("UPDATE workspaces SET icon = $1 WHERE id = 1")
+            .bind(icon.url())
+            .execute(&state.pool)
+            .await?;
+
+        state.chat_delete(1, 1, 1).await?;
+        assert!(state.get_chat_by_id(1).await?.is_none());
+        assert!(!path.exists());
+        assert!(icon_path.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_chat_by_workspace_owner_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // user 5 owns the workspace but isn't a member of private channel 2
+        state.update_workspace_owner(1, 5).await?;
+        assert!(state.get_chat_member(2, 5).await?.is_none());
+        state.chat_delete(2, 5, 1).await?;
+        assert!(state.get_chat_by_id(2).await?.is_none());
+
+        // chats of other workspaces can't be found
+        let err = state.chat_delete(4, 5, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_chat_by_non_owner_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let err = state.chat_delete(1, 2, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        assert!(state.get_chat_by_id(1).await?.is_some());
+
+        let err = state.chat_delete(100, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn chat_get_by_id_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -189,8 +1029,188 @@ mod tests {
     #[tokio::test]
     async fn chat_fetch_all_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let chats = state.fetch_chats(1, 1).await?;
+        let chats = state.fetch_chats(ListChats::default(), 1, 1).await?;
         assert_eq!(chats.len(), 4);
+        let chat = chats.iter().find(|c| c.chat.id == 1).expect("chat 1");
+        assert_eq!(chat.last_message.as_ref().map(|m| m.id), Some(10));
+        let chat = chats.iter().find(|c| c.chat.id == 2).expect("chat 2");
+        assert!(chat.last_message.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_fetch_should_order_by_activity() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "hello group".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        let message = state.message_create(input, 4, 1).await?;
+
+        let input = ListChats {
+            limit: 2,
+            ..Default::default()
+        };
+        let chats = state.fetch_chats(input, 1, 1).await?;
+        let ids: Vec<_> = chats.iter().map(|c| c.chat.id).collect();
+        // the other chats have the same activity time from the fixture, newer chats first
+        assert_eq!(ids, vec![4, 3]);
+        assert_eq!(chats[0].last_message, Some(message));
+
+        let input = ListChats {
+            last_id: Some(3),
+            ..Default::default()
+        };
+        let chats = state.fetch_chats(input, 1, 1).await?;
+        let ids: Vec<_> = chats.iter().map(|c| c.chat.id).collect();
+        assert_eq!(ids, vec![2, 1]);
+
+        let input = ListChats {
+            r#type: Some(ChatType::Single),
+            ..Default::default()
+        };
+        let chats = state.fetch_chats(input, 1, 1).await?;
+        assert_eq!(chats.len(), 1);
+        assert_eq!(chats[0].chat.r#type, ChatType::Single);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_mark_read_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chats = state.fetch_chats(ListChats::default(), 2, 1).await?;
+        let chat = chats.iter().find(|c| c.chat.id == 1).expect("chat 1");
+        // 10 messages in chat 1, 2 of them sent by user 2
+        assert_eq!(chat.unread_count, 8);
+        assert_eq!(chat.last_read_message_id, None);
+
+        let input = MarkRead {
+            message_id: Some(5),
+        };
+        let read = state.chat_mark_read(input, 1, 2).await?;
+        assert_eq!(read.last_read_message_id, 5);
+
+        // read position never goes backwards
+        let input = MarkRead {
+            message_id: Some(3),
+        };
+        let read = state.chat_mark_read(input, 1, 2).await?;
+        assert_eq!(read.last_read_message_id, 5);
+
+        let chats = state.fetch_chats(ListChats::default(), 2, 1).await?;
+        let chat = chats.iter().find(|c| c.chat.id == 1).expect("chat 1");
+        assert_eq!(chat.unread_count, 4);
+        assert_eq!(chat.last_read_message_id, Some(5));
+
+        let read = state.chat_mark_read(MarkRead::default(), 1, 2).await?;
+        assert_eq!(read.last_read_message_id, 10);
+
+        // message 1 is not in chat 2
+        let input = MarkRead {
+            message_id: Some(1),
+        };
+        let err = state.chat_mark_read(input, 2, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_fetch_channels_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateChat::new("random", &[2, 3], true);
+        let random = state.chat_create(input, 2, 1).await?;
+
+        let channels = state.fetch_channels(ListChannels::default(), 1, 1).await?;
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0].chat.name.as_deref(), Some("general"));
+        assert_eq!(channels[0].member_count, 5);
+        assert!(channels[0].joined);
+        assert_eq!(channels[1].chat.id, random.id);
+        assert_eq!(channels[1].member_count, 2);
+        assert!(!channels[1].joined);
+
+        let input = ListChannels {
+            q: Some("RAND".to_string()),
+            ..Default::default()
+        };
+        let channels = state.fetch_channels(input, 1, 1).await?;
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].chat.id, random.id);
+
+        // wildcards don't match any name
+        for q in ["%", "_", "ran_om"] {
+            let input = ListChannels {
+                q: Some(q.to_string()),
+                ..Default::default()
+            };
+            let channels = state.fetch_channels(input, 1, 1).await?;
+            assert!(channels.is_empty(), "{} should match nothing", q);
+        }
+
+        let input = ListChannels {
+            last_id: Some(1),
+            limit: 1,
+            ..Default::default()
+        };
+        let channels = state.fetch_channels(input, 1, 1).await?;
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].chat.id, random.id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_join_and_leave_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateChat::new("random", &[2, 3], true);
+        let random = state.chat_create(input, 2, 1).await?;
+
+        let chat = state.chat_join(random.id as _, 1, 1).await?;
+        assert_eq!(chat.members, vec![2, 3, 1]);
+        // joining again changes nothing
+        let chat = state.chat_join(random.id as _, 1, 1).await?;
+        assert_eq!(chat.members, vec![2, 3, 1]);
+
+        state.chat_leave(random.id as _, 3).await?;
+        let chat = state.get_chat_by_id(random.id as _).await?.unwrap();
+        assert_eq!(chat.members, vec![2, 1]);
+
+        let err = state.chat_leave(random.id as _, 2).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "update chat error: The owner can't leave chat {}",
+                random.id
+            )
+        );
+
+        // private channel 2 can't be joined
+        let err = state.chat_join(2, 5, 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "not found: chat id 2");
+
+        let err = state.chat_leave(3, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "update chat error: Can't leave a single chat"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_typing_should_notify() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener.listen("chat_typing").await?;
+
+        state.chat_typing(3, 1).await?;
+        let notif = listener.recv().await?;
+        let payload: serde_json::Value = serde_json::from_str(notif.payload())?;
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "typing": { "chat_id": 3, "user_id": 1 },
+            })
+        );
         Ok(())
     }
 
@@ -0,0 +1,403 @@
+use super::{token::hash_token, workspace::add_workspace_member, WorkspaceRole};
+use crate::{mailer::Mail, AppError, AppState};
+use chat_core::{User, Workspace};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+const DEFAULT_INVITATION_TTL_HOURS: u32 = 72;
+const MAX_INVITATION_TTL_HOURS: u32 = 30 * 24;
+
+/// Invitation to join a workspace, the token is shared by email or as a link
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Invitation {
+    pub id: i64,
+    #[serde(alias = "wsId")]
+    pub ws_id: i64,
+    /// only returned when the invitation is created, just its hash is stored
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub email: Option<String>,
+    #[serde(alias = "wsRole")]
+    pub ws_role: WorkspaceRole,
+    #[serde(alias = "createdBy")]
+    pub created_by: i64,
+    #[serde(alias = "expiresAt")]
+    pub expires_at: DateTime<Utc>,
+    #[serde(alias = "acceptedBy")]
+    pub accepted_by: Option<i64>,
+    #[serde(alias = "acceptedAt")]
+    pub accepted_at: Option<DateTime<Utc>>,
+    #[serde(alias = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct AcceptInvitation {
+    pub token: String,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, Default)]
+pub struct CreateInvitation {
+    /// Email to send the invitation to, only this email could accept it.
+    /// Without it the invitation is shared as a link and anyone with it could join
+    #[serde(default)]
+    pub email: Option<String>,
+    /// admin or member
+    #[serde(default)]
+    pub role: WorkspaceRole,
+    /// Hours until the invitation expires, 72 by default and 720 at most
+    #[serde(default)]
+    pub expires_in_hours: Option<u32>,
+}
+
+impl AppState {
+    /// Invite someone to the workspace of the user, only the owner and admins could do it and
+    /// only the owner could invite admins
+    pub async fn invitation_create(
+        &self,
+        input: CreateInvitation,
+        user: &User,
+    ) -> Result<Invitation, AppError> {
+        self.require_workspace_admin(user, "invite users to")
+            .await?;
+        if input.role == WorkspaceRole::Owner {
+            return Err(AppError::InvitationError(
+                "Workspace owner can't be invited".to_string(),
+            ));
+        }
+        if input.role == WorkspaceRole::Admin {
+            self.require_workspace_owner(user, "invite admins to")
+                .await?;
+        }
+        let hours = input
+            .expires_in_hours
+            .unwrap_or(DEFAULT_INVITATION_TTL_HOURS)
+            .clamp(1, MAX_INVITATION_TTL_HOURS);
+        let expires_at = Utc::now() + Duration::hours(hours as _);
+
+        let token = Uuid::new_v4().simple().to_string();
+        // the mail is sent before commit, the invitation is gone if it can't be sent
+        let mut tx = self.pool.begin().await?;
+        let mut invitation: Invitation = sqlx::query_as(
+            "
+            INSERT INTO workspace_invitations
+                (ws_id, token_hash, email, ws_role, created_by, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, ws_id, email, ws_role, created_by, expires_at, accepted_by,
+                accepted_at, created_at
+            ",
+        )
+        .bind(user.ws_id)
+        .bind(hash_token(&token))
+        .bind(&input.email)
+        .bind(input.role)
+        .bind(user.id)
+        .bind(expires_at)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if let Some(email) = &invitation.email {
+            let ws = self
+                .find_workspace_by_id(user.ws_id as _)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("workspace id {}", user.ws_id)))?;
+            let mail = Mail {
+                to: email.clone(),
+                subject: format!("You're invited to join {}", ws.name),
+                body: format!(
+                    "{} invited you to join {}. Sign up with the invitation {} before {}.",
+                    user.fullname, ws.name, token, invitation.expires_at
+                ),
+            };
+            self.mailer.send(mail).await?;
+        }
+        tx.commit().await?;
+
+        invitation.token = Some(token);
+        Ok(invitation)
+    }
+
+    /// Invitations of the workspace not accepted nor expired yet
+    pub async fn list_invitations(&self, user: &User) -> Result<Vec<Invitation>, AppError> {
+        self.require_workspace_admin(user, "list invitations of")
+            .await?;
+        let invitations = sqlx::query_as(
+            "
+            SELECT id, ws_id, email, ws_role, created_by, expires_at, accepted_by,
+                accepted_at, created_at
+            FROM workspace_invitations
+            WHERE ws_id = $1 AND accepted_at IS NULL AND expires_at > NOW()
+            ORDER BY id
+            ",
+        )
+        .bind(user.ws_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(invitations)
+    }
+
+    /// Join the workspace of the invitation as an existing user, the active workspace stays
+    pub async fn invitation_accept(
+        &self,
+        input: AcceptInvitation,
+        user: &User,
+    ) -> Result<Workspace, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let invitation = lock_invitation(&mut tx, &input.token, &user.email).await?;
+        let ws_id = invitation.ws_id as u64;
+        if let Some(member) = self.get_workspace_member(ws_id, user.id as _).await? {
+            // a deactivated member could only be reactivated by an admin
+            let reason = match member.deactivated_at {
+                Some(_) => "is deactivated in",
+                None => "is already a member of",
+            };
+            return Err(AppError::InvitationError(format!(
+                "User {} {} workspace {}",
+                user.id, reason, ws_id
+            )));
+        }
+        let Some(ws) = self.find_workspace_by_id(ws_id).await? else {
+            return Err(AppError::NotFound(format!("workspace id {}", ws_id)));
+        };
+        add_workspace_member(&mut tx, ws.id, user.id, invitation.ws_role).await?;
+        accept_invitation(&mut tx, invitation.id, user.id).await?;
+        tx.commit().await?;
+        Ok(ws)
+    }
+
+    /// Revoke an invitation not accepted yet
+    pub async fn invitation_revoke(&self, id: u64, user: &User) -> Result<(), AppError> {
+        self.require_workspace_admin(user, "revoke invitations of")
+            .await?;
+        let ret = sqlx::query(
+            "
+            DELETE FROM workspace_invitations
+            WHERE id = $1 AND ws_id = $2 AND accepted_at IS NULL
+            ",
+        )
+        .bind(id as i64)
+        .bind(user.ws_id)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("invitation id {}", id)));
+        }
+        Ok(())
+    }
+}
+
+/// Lock the invitation to accept it with the email, it's valid until accepted or expired
+pub(crate) async fn lock_invitation(
+    conn: &mut PgConnection,
+    token: &str,
+    email: &str,
+) -> Result<Invitation, AppError> {
+    let invitation: Option<Invitation> = sqlx::query_as(
+        "
+        SELECT id, ws_id, email, ws_role, created_by, expires_at, accepted_by,
+            accepted_at, created_at
+        FROM workspace_invitations
+        WHERE token_hash = $1
+        FOR UPDATE
+        ",
+    )
+    .bind(hash_token(token))
+    .fetch_optional(conn)
+    .await?;
+
+    let Some(invitation) = invitation else {
+        return Err(AppError::InvitationError("Invalid invitation".to_string()));
+    };
+    if invitation.accepted_at.is_some() {
+        return Err(AppError::InvitationError(
+            "Invitation has been used".to_string(),
+        ));
+    }
+    if invitation.expires_at <= Utc::now() {
+        return Err(AppError::InvitationError(
+            "Invitation has expired".to_string(),
+        ));
+    }
+    if invitation
+        .email
+        .as_ref()
+        .is_some_and(|v| !v.eq_ignore_ascii_case(email))
+    {
+        return Err(AppError::InvitationError(
+            "Invitation is for another email".to_string(),
+        ));
+    }
+    Ok(invitation)
+}
+
+pub(crate) async fn accept_invitation(
+    conn: &mut PgConnection,
+    id: i64,
+    user_id: i64,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "
+        UPDATE workspace_invitations
+        SET accepted_by = $2, accepted_at = NOW()
+        WHERE id = $1
+        ",
+    )
+    .bind(id)
+    .bind(user_id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{CreateUser, SigninUser, UpdateWorkspaceMember};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn invitation_should_be_required_to_join_owned_workspace() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.update_workspace_owner(1, 1).await?;
+        assert_eq!(owner.owner_id, 1);
+
+        let input = CreateUser::new("acme", "New Meng", "new@123.com", "hunter42");
+        let err = state.user_create(&input).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: Workspace acme could only be joined by invitation"
+        );
+
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let input = CreateInvitation {
+            email: Some("new@123.com".to_string()),
+            role: WorkspaceRole::Admin,
+            ..Default::default()
+        };
+        let invitation = state.invitation_create(input, &user).await?;
+        assert_eq!(invitation.ws_id, 1);
+        // the token is only returned on creation
+        let invitations = state.list_invitations(&user).await?;
+        assert_eq!(invitations.len(), 1);
+        assert!(invitations[0].token.is_none());
+
+        let mut input = CreateUser::new("", "New Meng", "other@123.com", "hunter42");
+        input.invite = invitation.token.clone();
+        let err = state.user_create(&input).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invitation error: Invitation is for another email"
+        );
+
+        input.email = "New@123.com".to_string();
+        let new_user = state.user_create(&input).await?;
+        assert_eq!(new_user.ws_id, 1);
+        assert_eq!(new_user.ws_name, "acme");
+        assert_eq!(
+            state.get_workspace_role(1, new_user.id as _).await?,
+            Some(WorkspaceRole::Admin)
+        );
+        assert!(state.list_invitations(&user).await?.is_empty());
+
+        let signin = SigninUser::new(&input.email, &input.password);
+        assert!(state.user_verify(&signin).await?.is_some());
+
+        // an invitation could only be used once
+        input.email = "again@123.com".to_string();
+        let err = state.user_create(&input).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invitation error: Invitation has been used"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invitation_create_should_check_role() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let user = state.find_user_by_id(2).await?.unwrap();
+        let err = state
+            .invitation_create(CreateInvitation::default(), &user)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 2 can't invite users to workspace 1"
+        );
+
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let input = CreateInvitation {
+            role: WorkspaceRole::Owner,
+            ..Default::default()
+        };
+        let err = state.invitation_create(input, &owner).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invitation error: Workspace owner can't be invited"
+        );
+
+        // admins could invite members but not other admins
+        let input = UpdateWorkspaceMember {
+            role: Some(WorkspaceRole::Admin),
+            ..Default::default()
+        };
+        state.workspace_member_update(2, input, &owner).await?;
+        state
+            .invitation_create(CreateInvitation::default(), &user)
+            .await?;
+        let input = CreateInvitation {
+            role: WorkspaceRole::Admin,
+            ..Default::default()
+        };
+        let err = state
+            .invitation_create(input.clone(), &user)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 2 can't invite admins to workspace 1"
+        );
+        state.invitation_create(input, &owner).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_or_revoked_invitation_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let invitation = state
+            .invitation_create(CreateInvitation::default(), &owner)
+            .await?;
+        sqlx::query("UPDATE workspace_invitations SET expires_at = NOW() WHERE id = $1")
+            .bind(invitation.id)
+            .execute(&state.pool)
+            .await?;
+
+        let mut input = CreateUser::new("", "Late Meng", "late@123.com", "hunter42");
+        input.invite = invitation.token.clone();
+        let err = state.user_create(&input).await.unwrap_err();
+        assert_eq!(err.to_string(), "invitation error: Invitation has expired");
+
+        let invitation = state
+            .invitation_create(CreateInvitation::default(), &owner)
+            .await?;
+        state.invitation_revoke(invitation.id as _, &owner).await?;
+        input.invite = invitation.token;
+        let err = state.user_create(&input).await.unwrap_err();
+        assert_eq!(err.to_string(), "invitation error: Invalid invitation");
+
+        let err = state
+            .invitation_revoke(invitation.id as _, &owner)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+use crate::{AppError, AppState};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::fmt;
+use utoipa::ToSchema;
+
+#[derive(
+    Debug, Clone, Copy, Default, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type,
+)]
+#[sqlx(type_name = "chat_role", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum ChatRole {
+    Owner,
+    Admin,
+    #[default]
+    Member,
+}
+
+/// What a member could do in a chat besides sending messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatCapability {
+    /// rename the chat, or switch it between public and private
+    Rename,
+    Invite,
+    /// remove other members
+    Remove,
+    /// delete messages of other members
+    DeleteMessages,
+    /// pin messages to the chat, or unpin them
+    Pin,
+    /// delete the chat with all its messages
+    Delete,
+    /// make members admins or not
+    ManageRoles,
+}
+
+/// Membership of a user in a chat, verify_chat puts it into the request extensions
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ChatMember {
+    #[serde(alias = "chatId")]
+    pub chat_id: i64,
+    #[serde(alias = "userId")]
+    pub user_id: i64,
+    pub role: ChatRole,
+    #[serde(alias = "joinedAt")]
+    pub joined_at: DateTime<Utc>,
+    pub muted: bool,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, Default)]
+pub struct UpdateChatMember {
+    /// admin or member, the owner can't be changed
+    pub role: ChatRole,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, Default)]
+pub struct UpdateMemberSettings {
+    #[serde(default)]
+    pub muted: Option<bool>,
+}
+
+impl ChatRole {
+    pub fn can(&self, capability: ChatCapability) -> bool {
+        match self {
+            ChatRole::Owner => true,
+            ChatRole::Admin => !matches!(
+                capability,
+                ChatCapability::Delete | ChatCapability::ManageRoles
+            ),
+            ChatRole::Member => {
+                matches!(capability, ChatCapability::Invite | ChatCapability::Pin)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ChatCapability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = match self {
+            ChatCapability::Rename => "rename",
+            ChatCapability::Invite => "invite members to",
+            ChatCapability::Remove => "remove members from",
+            ChatCapability::DeleteMessages => "delete messages in",
+            ChatCapability::Pin => "pin messages in",
+            ChatCapability::Delete => "delete",
+            ChatCapability::ManageRoles => "manage roles in",
+        };
+        write!(f, "{}", v)
+    }
+}
+
+impl ChatMember {
+    pub fn can(&self, capability: ChatCapability) -> bool {
+        self.role.can(capability)
+    }
+
+    pub fn require(&self, capability: ChatCapability) -> Result<(), AppError> {
+        if self.can(capability) {
+            return Ok(());
+        }
+        Err(AppError::PermissionDenied(format!(
+            "User {} can't {} chat {}",
+            self.user_id, capability, self.chat_id
+        )))
+    }
+}
+
+impl AppState {
+    pub async fn get_chat_member(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Option<ChatMember>, AppError> {
+        let member = sqlx::query_as(
+            "
+            SELECT chat_id, user_id, role, joined_at, muted
+            FROM chat_members
+            WHERE chat_id = $1 AND user_id = $2
+            ",
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(member)
+    }
+
+    /// Members of the chat, in the order they joined
+    pub async fn list_chat_members(&self, chat_id: u64) -> Result<Vec<ChatMember>, AppError> {
+        let members = sqlx::query_as(
+            "
+            SELECT chat_id, user_id, role, joined_at, muted
+            FROM chat_members
+            WHERE chat_id = $1
+            ORDER BY joined_at, user_id
+            ",
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(members)
+    }
+
+    /// Change the role of another member, only the owner could do it
+    pub async fn chat_member_update(
+        &self,
+        member: &ChatMember,
+        user_id: u64,
+        input: UpdateChatMember,
+    ) -> Result<ChatMember, AppError> {
+        member.require(ChatCapability::ManageRoles)?;
+        if input.role == ChatRole::Owner {
+            return Err(AppError::UpdateChatError(
+                "Chat owner can't be changed".to_string(),
+            ));
+        }
+
+        let updated = sqlx::query_as(
+            "
+            UPDATE chat_members
+            SET role = $3
+            WHERE chat_id = $1 AND user_id = $2 AND role <> 'owner'
+            RETURNING chat_id, user_id, role, joined_at, muted
+            ",
+        )
+        .bind(member.chat_id)
+        .bind(user_id as i64)
+        .bind(input.role)
+        .fetch_optional(&self.pool)
+        .await?;
+        match updated {
+            Some(updated) => Ok(updated),
+            None if user_id == member.user_id as u64 => Err(AppError::UpdateChatError(
+                "Chat owner can't be changed".to_string(),
+            )),
+            None => Err(AppError::NotFound(format!(
+                "member {} of chat {}",
+                user_id, member.chat_id
+            ))),
+        }
+    }
+
+    /// Change the settings of the member for the chat
+    pub async fn chat_member_settings_update(
+        &self,
+        member: &ChatMember,
+        input: UpdateMemberSettings,
+    ) -> Result<ChatMember, AppError> {
+        let updated = sqlx::query_as(
+            "
+            UPDATE chat_members
+            SET muted = COALESCE($3, muted)
+            WHERE chat_id = $1 AND user_id = $2
+            RETURNING chat_id, user_id, role, joined_at, muted
+            ",
+        )
+        .bind(member.chat_id)
+        .bind(member.user_id)
+        .bind(input.muted)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CreateChat;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn chat_members_should_follow_chat_members() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let members = state.list_chat_members(2).await?;
+        let ids: Vec<_> = members.iter().map(|m| m.user_id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(members[0].role, ChatRole::Owner);
+        assert_eq!(members[1].role, ChatRole::Member);
+
+        let input = CreateChat::new("random", &[2, 3], true);
+        let chat = state.chat_create(input, 2, 1).await?;
+        state.chat_join(chat.id as _, 1, 1).await?;
+        let member = state.get_chat_member(chat.id as _, 1).await?.unwrap();
+        assert_eq!(member.role, ChatRole::Member);
+        let owner = state.get_chat_member(chat.id as _, 2).await?.unwrap();
+        assert_eq!(owner.role, ChatRole::Owner);
+
+        state.chat_leave(chat.id as _, 1).await?;
+        assert!(state.get_chat_member(chat.id as _, 1).await?.is_none());
+
+        // the owner is never removed, even bypassing chat_update and chat_leave
+        let ret = sqlx::query("UPDATE chats SET members = array_remove(members, 2) WHERE id = $1")
+            .bind(chat.id)
+            .execute(&state.pool)
+            .await;
+        assert!(ret.is_err());
+        let owner = state.get_chat_member(chat.id as _, 2).await?.unwrap();
+        assert_eq!(owner.role, ChatRole::Owner);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_member_update_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.get_chat_member(2, 1).await?.unwrap();
+        let input = UpdateChatMember {
+            role: ChatRole::Admin,
+        };
+        let admin = state.chat_member_update(&owner, 2, input).await?;
+        assert_eq!(admin.role, ChatRole::Admin);
+        assert!(admin.can(ChatCapability::Remove));
+        assert!(!admin.can(ChatCapability::ManageRoles));
+        assert!(!admin.can(ChatCapability::Delete));
+
+        // admins can't manage roles
+        let input = UpdateChatMember {
+            role: ChatRole::Admin,
+        };
+        let err = state
+            .chat_member_update(&admin, 3, input)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 2 can't manage roles in chat 2"
+        );
+
+        let input = UpdateChatMember {
+            role: ChatRole::Owner,
+        };
+        let err = state
+            .chat_member_update(&owner, 3, input)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "update chat error: Chat owner can't be changed"
+        );
+
+        let input = UpdateChatMember {
+            role: ChatRole::Admin,
+        };
+        let err = state
+            .chat_member_update(&owner, 5, input)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "not found: member 5 of chat 2");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_member_settings_update_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let member = state.get_chat_member(1, 3).await?.unwrap();
+        assert!(!member.muted);
+        let input = UpdateMemberSettings { muted: Some(true) };
+        let member = state.chat_member_settings_update(&member, input).await?;
+        assert!(member.muted);
+        let member = state
+            .chat_member_settings_update(&member, UpdateMemberSettings::default())
+            .await?;
+        assert!(member.muted);
+        Ok(())
+    }
+}
@@ -1,14 +1,35 @@
-use crate::{models::ChatFile, AppError, AppState};
-use chat_core::Message;
+use crate::{
+    models::{ChatCapability, ChatFile},
+    AppError, AppState,
+};
+use chat_core::{Message, MessageRevision};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 use std::str::FromStr;
 use utoipa::{IntoParams, ToSchema};
 
+/// ts_headline marks the matched terms with these, they become `<b></b>` once the content is
+/// escaped
+const HIGHLIGHT_START: char = '\u{E000}';
+const HIGHLIGHT_STOP: char = '\u{E001}';
+
 #[derive(Debug, ToSchema, Serialize, Deserialize)]
 pub struct CreateMessage {
     pub content: String,
     #[serde(default)]
     pub files: Vec<String>,
+    /// Message replied to, the new message joins the thread of that message
+    #[serde(default)]
+    pub reply_to: Option<u64>,
+}
+
+#[derive(Debug, ToSchema, Serialize, Deserialize)]
+pub struct UpdateMessage {
+    pub content: String,
+    /// New files of the message, keep the current files if not provided
+    #[serde(default)]
+    pub files: Option<Vec<String>>,
 }
 
 #[derive(Debug, IntoParams, ToSchema, Serialize, Deserialize)]
@@ -19,6 +40,37 @@ pub struct ListMessage {
     pub limit: u64,
 }
 
+#[derive(Debug, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct SearchMessage {
+    /// Search terms, supports quoted phrases, `or` and `-` to exclude a term
+    pub q: String,
+    #[serde(default)]
+    pub chat_id: Option<u64>,
+    #[serde(default)]
+    pub sender_id: Option<u64>,
+    /// Messages created at or after this time
+    #[serde(default)]
+    pub from: Option<DateTime<Utc>>,
+    /// Messages created before this time
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub has_files: Option<bool>,
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+#[derive(Debug, ToSchema, FromRow, Serialize, Deserialize)]
+pub struct SearchResult {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub message: Message,
+    /// Fragments of the content with matched terms wrapped in `<b></b>`, the rest is html escaped
+    pub snippet: String,
+}
+
 #[allow(dead_code)]
 impl AppState {
     pub async fn message_create(
@@ -27,41 +79,265 @@ impl AppState {
         chat_id: u64,
         user_id: u64,
     ) -> Result<Message, AppError> {
-        let base_dir = &self.config.server.base_dir;
-        if input.content.is_empty() {
-            return Err(AppError::CreateMessageError(
-                "Content can't be empty".to_string(),
-            ));
+        self.validate_message(&input.content, &input.files, AppError::CreateMessageError)?;
+
+        let thread_root_id = match input.reply_to {
+            Some(reply_to) => match self.get_message_by_id(chat_id, reply_to).await? {
+                Some(parent) if parent.deleted_at.is_none() => {
+                    Some(parent.thread_root_id.unwrap_or(parent.id))
+                }
+                _ => {
+                    return Err(AppError::CreateMessageError(format!(
+                        "reply to message {} doesn't exist",
+                        reply_to
+                    )))
+                }
+            },
+            None => None,
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let message: Message = sqlx::query_as(
+            "
+            INSERT INTO messages (chat_id, sender_id, content, files, reply_to, thread_root_id)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, chat_id, sender_id, content, files, reply_to, thread_root_id,
+                reply_count, created_at, edited_at, deleted_at
+            ",
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(input.content)
+        .bind(input.files)
+        .bind(input.reply_to.map(|v| v as i64))
+        .bind(thread_root_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if let Some(thread_root_id) = thread_root_id {
+            sqlx::query("UPDATE messages SET reply_count = reply_count + 1 WHERE id = $1")
+                .bind(thread_root_id)
+                .execute(&mut *tx)
+                .await?;
         }
-        for s in &input.files {
-            let file = ChatFile::from_str(s)?;
-            if !file.path(base_dir).exists() {
-                return Err(AppError::CreateMessageError(format!(
-                    "file {} doesn't exist",
-                    s
+
+        sqlx::query("UPDATE chats SET last_message_id = $1, last_activity_at = $2 WHERE id = $3")
+            .bind(message.id)
+            .bind(message.created_at)
+            .bind(chat_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(message)
+    }
+
+    /// Edit a message, the prior revision is kept in message_revisions
+    pub async fn message_update(
+        &self,
+        input: UpdateMessage,
+        chat_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let message: Option<Message> = sqlx::query_as(
+            "
+            SELECT id, chat_id, sender_id, content, files, reply_to, thread_root_id,
+                reply_count, created_at, edited_at, deleted_at
+            FROM messages
+            WHERE id = $1 AND chat_id = $2
+            FOR UPDATE
+            ",
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        // deleted messages can't be edited
+        let Some(message) = message.filter(|m| m.deleted_at.is_none()) else {
+            return Err(AppError::NotFound(format!("message id {}", id)));
+        };
+        if message.sender_id != user_id as i64 {
+            return Err(AppError::PermissionDenied(format!(
+                "User {} can't edit message {}",
+                user_id, id
+            )));
+        }
+
+        let files = input.files.unwrap_or_else(|| message.files.clone());
+        self.validate_message(&input.content, &files, AppError::UpdateMessageError)?;
+
+        sqlx::query(
+            "
+            INSERT INTO message_revisions (message_id, content, files, created_at)
+            VALUES ($1, $2, $3, $4)
+            ",
+        )
+        .bind(message.id)
+        .bind(message.content)
+        .bind(message.files)
+        .bind(message.edited_at.unwrap_or(message.created_at))
+        .execute(&mut *tx)
+        .await?;
+
+        let message = sqlx::query_as(
+            "
+            UPDATE messages
+            SET content = $1, files = $2, edited_at = NOW()
+            WHERE id = $3
+            RETURNING id, chat_id, sender_id, content, files, reply_to, thread_root_id,
+                reply_count, created_at, edited_at, deleted_at
+            ",
+        )
+        .bind(input.content)
+        .bind(files)
+        .bind(id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(message)
+    }
+
+    /// Delete a message and leave a tombstone. The sender could delete their own messages,
+    /// members allowed to delete messages and the workspace owner could delete anyone's.
+    /// The workspace owner doesn't need to be a member of the chat
+    pub async fn message_delete(
+        &self,
+        chat_id: u64,
+        id: u64,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<(), AppError> {
+        let chat = self.get_chat_by_id(chat_id).await?;
+        let Some(chat) = chat.filter(|v| v.ws_id == ws_id as i64) else {
+            return Err(AppError::NotFound(format!("chat id {}", chat_id)));
+        };
+        let Some(message) = self.get_message_by_id(chat_id, id).await? else {
+            return Err(AppError::NotFound(format!("message id {}", id)));
+        };
+        if message.deleted_at.is_some() {
+            return Ok(());
+        }
+
+        let member = self.get_chat_member(chat_id, user_id).await?;
+        let allowed = member.is_some_and(|v| {
+            v.user_id == message.sender_id || v.can(ChatCapability::DeleteMessages)
+        });
+        if !allowed {
+            let ws = self.find_workspace_by_id(chat.ws_id as _).await?;
+            if ws.map(|v| v.owner_id) != Some(user_id as i64) {
+                return Err(AppError::PermissionDenied(format!(
+                    "User {} can't delete message {}",
+                    user_id, id
                 )));
             }
         }
+
+        let mut tx = self.pool.begin().await?;
+        let deleted: Option<(Option<i64>,)> = sqlx::query_as(
+            "
+            UPDATE messages
+            SET content = '', files = '{}', deleted_at = NOW()
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING thread_root_id
+            ",
+        )
+        .bind(id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        // deleted by a concurrent request
+        let Some((thread_root_id,)) = deleted else {
+            return Ok(());
+        };
+        if let Some(thread_root_id) = thread_root_id {
+            sqlx::query(
+                "UPDATE messages SET reply_count = GREATEST(reply_count - 1, 0) WHERE id = $1",
+            )
+            .bind(thread_root_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        // the chat list previews the latest message left
+        sqlx::query(
+            "
+            UPDATE chats
+            SET last_message_id = (
+                SELECT id FROM messages
+                WHERE chat_id = $1 AND deleted_at IS NULL
+                ORDER BY id DESC
+                LIMIT 1
+            )
+            WHERE id = $1 AND last_message_id = $2
+            ",
+        )
+        .bind(chat_id as i64)
+        .bind(id as i64)
+        .execute(&mut *tx)
+        .await?;
+        // prior revisions, reactions and pins go away with the message content
+        sqlx::query("DELETE FROM message_revisions WHERE message_id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM chat_pins WHERE message_id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM message_reactions WHERE message_id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_message_by_id(
+        &self,
+        chat_id: u64,
+        id: u64,
+    ) -> Result<Option<Message>, AppError> {
         let message = sqlx::query_as(
             "
-            INSERT INTO messages (chat_id, sender_id, content, files)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, chat_id, sender_id, content, files, created_at
+            SELECT id, chat_id, sender_id, content, files, reply_to, thread_root_id,
+                reply_count, created_at, edited_at, deleted_at
+            FROM messages
+            WHERE id = $1 AND chat_id = $2
             ",
         )
+        .bind(id as i64)
         .bind(chat_id as i64)
-        .bind(user_id as i64)
-        .bind(input.content)
-        .bind(input.files)
-        .fetch_one(&self.pool)
+        .fetch_optional(&self.pool)
         .await?;
         Ok(message)
     }
 
+    pub async fn list_message_revisions(
+        &self,
+        chat_id: u64,
+        id: u64,
+    ) -> Result<Vec<MessageRevision>, AppError> {
+        let revisions = sqlx::query_as(
+            "
+            SELECT r.id, r.message_id, r.content, r.files, r.created_at
+            FROM message_revisions r
+            JOIN messages m ON m.id = r.message_id
+            WHERE r.message_id = $1 AND m.chat_id = $2
+            ORDER BY r.id DESC
+            ",
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(revisions)
+    }
+
     pub async fn list_message(
         &self,
         input: ListMessage,
         chat_id: u64,
+        user_id: u64,
     ) -> Result<Vec<Message>, AppError> {
         let last_id = input.last_id.unwrap_or(i64::MAX as _);
         let limit = match input.limit {
@@ -69,9 +345,10 @@ impl AppState {
             1..=100 => input.limit as _,
             _ => 100,
         };
-        let messages = sqlx::query_as(
+        let mut messages = sqlx::query_as(
             "
-            SELECT id, chat_id, sender_id, content, files, created_at
+            SELECT id, chat_id, sender_id, content, files, reply_to, thread_root_id,
+                reply_count, created_at, edited_at, deleted_at
             FROM messages
             WHERE chat_id = $1
             AND id < $2
@@ -84,14 +361,174 @@ impl AppState {
         .bind(limit)
         .fetch_all(&self.pool)
         .await?;
+        self.load_reactions(&mut messages, user_id).await?;
+        Ok(messages)
+    }
+
+    /// List replies of a thread, paged backwards like list_message
+    pub async fn list_thread(
+        &self,
+        input: ListMessage,
+        chat_id: u64,
+        thread_root_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<Message>, AppError> {
+        if self
+            .get_message_by_id(chat_id, thread_root_id)
+            .await?
+            .is_none()
+        {
+            return Err(AppError::NotFound(format!("message id {}", thread_root_id)));
+        }
+
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let limit = match input.limit {
+            0 => i64::MAX,
+            1..=100 => input.limit as _,
+            _ => 100,
+        };
+        let mut messages = sqlx::query_as(
+            "
+            SELECT id, chat_id, sender_id, content, files, reply_to, thread_root_id,
+                reply_count, created_at, edited_at, deleted_at
+            FROM messages
+            WHERE chat_id = $1
+            AND thread_root_id = $2
+            AND id < $3
+            ORDER BY id DESC
+            LIMIT $4
+            ",
+        )
+        .bind(chat_id as i64)
+        .bind(thread_root_id as i64)
+        .bind(last_id as i64)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        self.load_reactions(&mut messages, user_id).await?;
         Ok(messages)
     }
+
+    /// Search messages in chats of the workspace the user belongs to, newest first
+    pub async fn search_messages(
+        &self,
+        input: SearchMessage,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<SearchResult>, AppError> {
+        if input.q.trim().is_empty() {
+            return Err(AppError::SearchError("Query can't be empty".to_string()));
+        }
+
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let limit = match input.limit {
+            0 => 20,
+            1..=100 => input.limit as _,
+            _ => 100,
+        };
+        let mut results: Vec<SearchResult> = sqlx::query_as(
+            "
+            SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.reply_to, m.thread_root_id,
+                m.reply_count, m.created_at, m.edited_at, m.deleted_at,
+                ts_headline('simple', m.content, q, $11) AS snippet
+            FROM messages m
+            JOIN chats c ON c.id = m.chat_id,
+            websearch_to_tsquery('simple', $1) q
+            WHERE c.ws_id = $2 AND $3 = ANY(c.members)
+            AND m.search_vector @@ q
+            AND m.deleted_at IS NULL
+            AND ($4::BIGINT IS NULL OR m.chat_id = $4)
+            AND ($5::BIGINT IS NULL OR m.sender_id = $5)
+            AND ($6::TIMESTAMPTZ IS NULL OR m.created_at >= $6)
+            AND ($7::TIMESTAMPTZ IS NULL OR m.created_at < $7)
+            AND ($8::BOOLEAN IS NULL OR (cardinality(m.files) > 0) = $8)
+            AND m.id < $9
+            ORDER BY m.id DESC
+            LIMIT $10
+            ",
+        )
+        .bind(input.q)
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(input.chat_id.map(|v| v as i64))
+        .bind(input.sender_id.map(|v| v as i64))
+        .bind(input.from)
+        .bind(input.to)
+        .bind(input.has_files)
+        .bind(last_id as i64)
+        .bind(limit)
+        .bind(format!(
+            "StartSel={}, StopSel={}, MaxFragments=2",
+            HIGHLIGHT_START, HIGHLIGHT_STOP
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        for result in results.iter_mut() {
+            result.snippet = highlight(&result.snippet);
+        }
+        Ok(results)
+    }
+
+    fn validate_message(
+        &self,
+        content: &str,
+        files: &[String],
+        err: impl Fn(String) -> AppError,
+    ) -> Result<(), AppError> {
+        let base_dir = &self.config.server.base_dir;
+        if content.is_empty() {
+            return Err(err("Content can't be empty".to_string()));
+        }
+        for s in files {
+            let file = ChatFile::from_str(s)?;
+            if !file.path(base_dir).exists() {
+                return Err(err(format!("file {} doesn't exist", s)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escape the snippet as html, the content is user input, and wrap the matched terms in `<b></b>`
+fn highlight(snippet: &str) -> String {
+    let mut ret = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            HIGHLIGHT_START => ret.push_str("<b>"),
+            HIGHLIGHT_STOP => ret.push_str("</b>"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '&' => ret.push_str("&amp;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&#39;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+impl SearchMessage {
+    pub fn new(q: &str) -> Self {
+        Self {
+            q: q.to_string(),
+            chat_id: None,
+            sender_id: None,
+            from: None,
+            to: None,
+            has_files: None,
+            last_id: None,
+            limit: 0,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::{ChatRole, UpdateChatMember};
     use anyhow::Result;
+    use sqlx::postgres::PgListener;
 
     #[tokio::test]
     async fn create_message_should_work() -> Result<()> {
@@ -99,6 +536,7 @@ mod tests {
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec![],
+            reply_to: None,
         };
         let message = state
             .message_create(input, 1, 1)
@@ -110,6 +548,7 @@ mod tests {
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec!["1".to_string()],
+            reply_to: None,
         };
 
         let err = state.message_create(input, 1, 1).await.unwrap_err();
@@ -120,6 +559,7 @@ mod tests {
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec![url],
+            reply_to: None,
         };
 
         let message = state
@@ -132,6 +572,357 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn create_large_message_should_notify_ids_only() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener
+            .listen_all(["chat_message_created", "chat_message_updated"])
+            .await?;
+
+        // way over the 8000 bytes limit of pg_notify payloads
+        let input = CreateMessage {
+            content: "a".repeat(20 * 1024),
+            files: vec![],
+            reply_to: None,
+        };
+        let message = state.message_create(input, 1, 1).await?;
+        assert_eq!(message.content.len(), 20 * 1024);
+
+        let notif = listener.recv().await?;
+        let payload: serde_json::Value = serde_json::from_str(notif.payload())?;
+        assert_eq!(
+            payload,
+            serde_json::json!({ "message_id": message.id, "chat_id": 1 })
+        );
+
+        let input = UpdateMessage {
+            content: "b".repeat(20 * 1024),
+            files: None,
+        };
+        state.message_update(input, 1, message.id as _, 1).await?;
+        let notif = listener.recv().await?;
+        assert_eq!(notif.channel(), "chat_message_updated");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateMessage {
+            content: "Hello, edited!".to_string(),
+            files: None,
+        };
+        let message = state.message_update(input, 1, 1, 1).await?;
+        assert_eq!(message.content, "Hello, edited!");
+        assert!(message.edited_at.is_some());
+
+        let input = UpdateMessage {
+            content: "Hello again!".to_string(),
+            files: Some(vec![upload_dummy_file(&state)?]),
+        };
+        let message = state.message_update(input, 1, 1, 1).await?;
+        assert_eq!(message.files.len(), 1);
+
+        let revisions = state.list_message_revisions(1, 1).await?;
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].content, "Hello, edited!");
+        assert_eq!(revisions[1].content, "Hello, world!");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_message_with_invalid_input_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // message 2 is sent by user 2
+        let input = UpdateMessage {
+            content: "hijack".to_string(),
+            files: None,
+        };
+        let err = state.message_update(input, 1, 2, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        let input = UpdateMessage {
+            content: "".to_string(),
+            files: None,
+        };
+        let err = state.message_update(input, 1, 1, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "update message error: Content can't be empty"
+        );
+
+        // message 1 doesn't belong to chat 2
+        let input = UpdateMessage {
+            content: "hello".to_string(),
+            files: None,
+        };
+        let err = state.message_update(input, 2, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_message_should_leave_tombstone() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // user 2 deletes own message 2
+        state.message_delete(1, 2, 2, 1).await?;
+        let message = state
+            .get_message_by_id(1, 2)
+            .await?
+            .expect("tombstone should exist");
+        assert!(message.deleted_at.is_some());
+        assert_eq!(message.content, "");
+
+        // chat owner (user 1) deletes message 3 sent by user 3
+        state.message_delete(1, 3, 1, 1).await?;
+
+        // tombstones stay in the list so pagination is stable
+        let input = ListMessage {
+            last_id: None,
+            limit: 0,
+        };
+        let messages = state.list_message(input, 1, 1).await?;
+        assert_eq!(messages.len(), 10);
+        assert_eq!(
+            messages.iter().filter(|m| m.deleted_at.is_some()).count(),
+            2
+        );
+
+        // deleted message can't be edited
+        let input = UpdateMessage {
+            content: "revive".to_string(),
+            files: None,
+        };
+        let err = state.message_update(input, 1, 2, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_message_by_others_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // user 2 is neither the sender nor an admin
+        let err = state.message_delete(1, 1, 2, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        // admins could delete messages of others
+        let owner = state.get_chat_member(1, 1).await?.unwrap();
+        let input = UpdateChatMember {
+            role: ChatRole::Admin,
+        };
+        state.chat_member_update(&owner, 2, input).await?;
+        state.message_delete(1, 1, 2, 1).await?;
+
+        let err = state.message_delete(1, 100, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        // chats of other workspaces can't be found
+        let err = state.message_delete(1, 5, 1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_message_by_workspace_owner_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "secret plan".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        let message = state.message_create(input, 2, 2).await?;
+
+        // user 5 isn't a member of private channel 2
+        let err = state
+            .message_delete(2, message.id as _, 5, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        state.update_workspace_owner(1, 5).await?;
+        state.message_delete(2, message.id as _, 5, 1).await?;
+        let message = state.get_message_by_id(2, message.id as _).await?.unwrap();
+        assert!(message.deleted_at.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_thread_reply_should_update_counts() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let (last_id,): (i64,) = sqlx::query_as("SELECT last_message_id FROM chats WHERE id = 1")
+            .fetch_one(&state.pool)
+            .await?;
+        let input = CreateMessage {
+            content: "reply".to_string(),
+            files: vec![],
+            reply_to: Some(1),
+        };
+        let reply = state.message_create(input, 1, 2).await?;
+
+        state.message_delete(1, reply.id as _, 2, 1).await?;
+        let root = state
+            .get_message_by_id(1, 1)
+            .await?
+            .expect("root should exist");
+        assert_eq!(root.reply_count, 0);
+        let (id,): (i64,) = sqlx::query_as("SELECT last_message_id FROM chats WHERE id = 1")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(id, last_id);
+
+        // deleting again changes nothing
+        state.message_delete(1, reply.id as _, 2, 1).await?;
+        let root = state.get_message_by_id(1, 1).await?.unwrap();
+        assert_eq!(root.reply_count, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn thread_reply_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "reply".to_string(),
+            files: vec![],
+            reply_to: Some(1),
+        };
+        let reply = state.message_create(input, 1, 2).await?;
+        assert_eq!(reply.reply_to, Some(1));
+        assert_eq!(reply.thread_root_id, Some(1));
+
+        // reply to a reply joins the same thread
+        let input = CreateMessage {
+            content: "reply to reply".to_string(),
+            files: vec![],
+            reply_to: Some(reply.id as _),
+        };
+        let message = state.message_create(input, 1, 3).await?;
+        assert_eq!(message.reply_to, Some(reply.id));
+        assert_eq!(message.thread_root_id, Some(1));
+
+        let root = state
+            .get_message_by_id(1, 1)
+            .await?
+            .expect("root should exist");
+        assert_eq!(root.reply_count, 2);
+
+        let input = ListMessage {
+            last_id: None,
+            limit: 1,
+        };
+        let messages = state.list_thread(input, 1, 1, 1).await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, message.id);
+
+        // message 1 is not in chat 2
+        let input = CreateMessage {
+            content: "reply".to_string(),
+            files: vec![],
+            reply_to: Some(1),
+        };
+        let err = state.message_create(input, 2, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "create message error: reply to message 1 doesn't exist"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let results = state
+            .search_messages(SearchMessage::new("hello"), 1, 1)
+            .await?;
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].message.id, 10);
+        assert_eq!(results[0].snippet, "<b>Hello</b>, world");
+
+        let input = SearchMessage {
+            last_id: Some(10),
+            limit: 2,
+            ..SearchMessage::new("hello")
+        };
+        let results = state.search_messages(input, 1, 1).await?;
+        assert_eq!(
+            results.iter().map(|r| r.message.id).collect::<Vec<_>>(),
+            vec![9, 6]
+        );
+
+        let input = SearchMessage {
+            sender_id: Some(2),
+            ..SearchMessage::new("hello or there")
+        };
+        let results = state.search_messages(input, 1, 1).await?;
+        assert_eq!(results.len(), 2);
+
+        let input = SearchMessage {
+            has_files: Some(true),
+            ..SearchMessage::new("hello")
+        };
+        let results = state.search_messages(input, 1, 1).await?;
+        assert!(results.is_empty());
+
+        let err = state
+            .search_messages(SearchMessage::new(" "), 1, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "search error: Query can't be empty");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_messages_should_escape_snippet() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "<script>alert('xss')</script> & <b>bold</b> secret".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        state.message_create(input, 1, 1).await?;
+
+        let results = state
+            .search_messages(SearchMessage::new("secret"), 1, 1)
+            .await?;
+        assert_eq!(results.len(), 1);
+        let snippet = &results[0].snippet;
+        assert!(snippet.contains("<b>secret</b>"));
+        assert!(!snippet.replace("<b>", "").replace("</b>", "").contains('<'));
+
+        assert_eq!(
+            highlight("<img src=x onerror=\"alert('xss')\"> & \u{E000}secret\u{E001}"),
+            "&lt;img src=x onerror=&quot;alert(&#39;xss&#39;)&quot;&gt; &amp; <b>secret</b>"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_messages_should_only_include_member_chats() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 2 is a private channel with members 1, 2, 3
+        let input = CreateMessage {
+            content: "secret plan".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        state.message_create(input, 2, 1).await?;
+
+        let results = state
+            .search_messages(SearchMessage::new("secret"), 1, 1)
+            .await?;
+        assert_eq!(results.len(), 1);
+
+        let results = state
+            .search_messages(SearchMessage::new("secret"), 4, 1)
+            .await?;
+        assert!(results.is_empty());
+
+        // other workspace can't see it
+        let results = state
+            .search_messages(SearchMessage::new("secret"), 1, 2)
+            .await?;
+        assert!(results.is_empty());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn list_message_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -139,7 +930,7 @@ mod tests {
             last_id: None,
             limit: 6,
         };
-        let messages = state.list_message(input, 1).await?;
+        let messages = state.list_message(input, 1, 1).await?;
         assert_eq!(messages.len(), 6);
 
         let last_id = messages.last().expect("last message should exist").id as u64;
@@ -147,7 +938,7 @@ mod tests {
             last_id: Some(last_id),
             limit: 6,
         };
-        let messages = state.list_message(input, 1).await?;
+        let messages = state.list_message(input, 1, 1).await?;
         assert_eq!(messages.len(), 4);
         Ok(())
     }
@@ -1,13 +1,29 @@
 mod chat;
 mod file;
+mod invitation;
+mod member;
 mod message;
+mod pin;
+mod reaction;
+mod token;
 mod user;
 mod workspace;
 
-pub use chat::CreateChat;
-pub use message::{CreateMessage, ListMessage};
+pub use chat::{
+    ChannelSummary, ChatSummary, CreateChat, ListChannels, ListChats, MarkRead, UpdateChat,
+};
+pub use invitation::{AcceptInvitation, CreateInvitation, Invitation};
+pub use member::{ChatCapability, ChatMember, ChatRole, UpdateChatMember, UpdateMemberSettings};
+pub use message::{CreateMessage, ListMessage, SearchMessage, SearchResult, UpdateMessage};
+pub use pin::ChatPin;
+pub use reaction::ReactionInput;
 use serde::{Deserialize, Serialize};
+pub use token::{RefreshToken, Signout};
 pub use user::{CreateUser, SigninUser};
+pub use workspace::{
+    TransferWorkspace, UpdateWorkspace, UpdateWorkspaceMember, WorkspaceMember, WorkspaceRole,
+    WorkspaceSummary,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatFile {
@@ -0,0 +1,122 @@
+use crate::{
+    models::{ChatCapability, ChatMember},
+    AppError, AppState,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ChatPin {
+    #[serde(alias = "chatId")]
+    pub chat_id: i64,
+    #[serde(alias = "messageId")]
+    pub message_id: i64,
+    #[serde(alias = "pinnedBy")]
+    pub pinned_by: i64,
+    #[serde(alias = "pinnedAt")]
+    pub pinned_at: DateTime<Utc>,
+}
+
+impl AppState {
+    /// Pin the message to the chat, pinning it again keeps the first pin
+    pub async fn message_pin(
+        &self,
+        member: &ChatMember,
+        message_id: u64,
+    ) -> Result<ChatPin, AppError> {
+        member.require(ChatCapability::Pin)?;
+        let chat_id = member.chat_id as u64;
+        match self.get_message_by_id(chat_id, message_id).await? {
+            Some(message) if message.deleted_at.is_none() => {}
+            _ => return Err(AppError::NotFound(format!("message id {}", message_id))),
+        }
+
+        let pin = sqlx::query_as(
+            "
+            INSERT INTO chat_pins (chat_id, message_id, pinned_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (chat_id, message_id) DO UPDATE SET chat_id = EXCLUDED.chat_id
+            RETURNING chat_id, message_id, pinned_by, pinned_at
+            ",
+        )
+        .bind(member.chat_id)
+        .bind(message_id as i64)
+        .bind(member.user_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(pin)
+    }
+
+    pub async fn message_unpin(
+        &self,
+        member: &ChatMember,
+        message_id: u64,
+    ) -> Result<(), AppError> {
+        member.require(ChatCapability::Pin)?;
+        let ret = sqlx::query("DELETE FROM chat_pins WHERE chat_id = $1 AND message_id = $2")
+            .bind(member.chat_id)
+            .bind(message_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "pin of message id {}",
+                message_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Pins of the chat, the latest first
+    pub async fn list_pins(&self, chat_id: u64) -> Result<Vec<ChatPin>, AppError> {
+        let pins = sqlx::query_as(
+            "
+            SELECT chat_id, message_id, pinned_by, pinned_at
+            FROM chat_pins
+            WHERE chat_id = $1
+            ORDER BY pinned_at DESC, message_id DESC
+            ",
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(pins)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn pin_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // members of chat 1 could pin messages
+        let member = state.get_chat_member(1, 2).await?.unwrap();
+        let pin = state.message_pin(&member, 1).await?;
+        assert_eq!(pin.message_id, 1);
+        assert_eq!(pin.pinned_by, 2);
+
+        let owner = state.get_chat_member(1, 1).await?.unwrap();
+        let again = state.message_pin(&owner, 1).await?;
+        assert_eq!(again, pin);
+        state.message_pin(&owner, 2).await?;
+        let pins = state.list_pins(1).await?;
+        assert_eq!(pins.len(), 2);
+
+        state.message_unpin(&member, 1).await?;
+        let err = state.message_unpin(&member, 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "not found: pin of message id 1");
+
+        // deleted messages are unpinned, and can't be pinned again
+        state.message_delete(1, 2, 1, 1).await?;
+        assert!(state.list_pins(1).await?.is_empty());
+        let err = state.message_pin(&owner, 2).await.unwrap_err();
+        assert_eq!(err.to_string(), "not found: message id 2");
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+use crate::{AppError, AppState};
+use chat_core::{Message, MessageReaction, ReactionSummary};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// custom_emojis.shortcode is VARCHAR(32)
+const MAX_SHORTCODE_LEN: usize = 32;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ReactionInput {
+    /// A unicode emoji, or a workspace custom emoji shortcode like `:rust:`
+    pub emoji: String,
+}
+
+#[derive(Debug, FromRow)]
+struct MessageReactionSummary {
+    message_id: i64,
+    #[sqlx(flatten)]
+    summary: ReactionSummary,
+}
+
+impl AppState {
+    pub async fn reaction_add(
+        &self,
+        input: ReactionInput,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<MessageReaction, AppError> {
+        if let Some(code) = parse_shortcode(&input.emoji) {
+            if !self.custom_emoji_exists(chat_id, code).await? {
+                return Err(AppError::ReactionError(format!(
+                    "Unknown custom emoji: {}",
+                    input.emoji
+                )));
+            }
+        } else if emojis::get(&input.emoji).is_none() {
+            // a single emoji, possibly with a skin tone or joined by zwj
+            return Err(AppError::ReactionError(format!(
+                "Invalid emoji: {}",
+                input.emoji
+            )));
+        }
+
+        match self.get_message_by_id(chat_id, message_id).await? {
+            Some(message) if message.deleted_at.is_none() => {}
+            _ => return Err(AppError::NotFound(format!("message id {}", message_id))),
+        }
+
+        sqlx::query(
+            "
+            INSERT INTO message_reactions (message_id, user_id, emoji)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            ",
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(&input.emoji)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(MessageReaction {
+            message_id: message_id as _,
+            chat_id: chat_id as _,
+            user_id: user_id as _,
+            emoji: input.emoji,
+        })
+    }
+
+    pub async fn reaction_remove(
+        &self,
+        input: ReactionInput,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            "
+            DELETE FROM message_reactions r
+            USING messages m
+            WHERE r.message_id = m.id AND m.chat_id = $1
+            AND r.message_id = $2 AND r.user_id = $3 AND r.emoji = $4
+            ",
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(&input.emoji)
+        .execute(&self.pool)
+        .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "reaction {} on message id {}",
+                input.emoji, message_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether the workspace of the chat has the custom emoji
+    async fn custom_emoji_exists(&self, chat_id: u64, shortcode: &str) -> Result<bool, AppError> {
+        let emoji = sqlx::query(
+            "
+            SELECT 1
+            FROM custom_emojis e
+            JOIN chats c ON c.ws_id = e.ws_id
+            WHERE c.id = $1 AND e.shortcode = $2
+            ",
+        )
+        .bind(chat_id as i64)
+        .bind(shortcode)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(emoji.is_some())
+    }
+
+    /// Fill in aggregated reactions of the messages, as seen by the user
+    pub(crate) async fn load_reactions(
+        &self,
+        messages: &mut [Message],
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+        let rows: Vec<MessageReactionSummary> = sqlx::query_as(
+            "
+            SELECT message_id, emoji, COUNT(*) AS count, bool_or(user_id = $2) AS reacted
+            FROM message_reactions
+            WHERE message_id = ANY($1)
+            GROUP BY message_id, emoji
+            ORDER BY MIN(created_at)
+            ",
+        )
+        .bind(&ids)
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut reactions: HashMap<i64, Vec<ReactionSummary>> = HashMap::new();
+        for row in rows {
+            reactions
+                .entry(row.message_id)
+                .or_default()
+                .push(row.summary);
+        }
+        for message in messages {
+            message.reactions = reactions.remove(&message.id).unwrap_or_default();
+        }
+        Ok(())
+    }
+}
+
+/// Shortcode of a custom emoji like `:rust:`, None for anything else
+fn parse_shortcode(emoji: &str) -> Option<&str> {
+    let code = emoji.strip_prefix(':')?.strip_suffix(':')?;
+    let valid = !code.is_empty()
+        && code.len() <= MAX_SHORTCODE_LEN
+        && code
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'));
+    valid.then_some(code)
+}
+
+#[cfg(test)]
+impl ReactionInput {
+    pub fn new(emoji: &str) -> Self {
+        Self {
+            emoji: emoji.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ListMessage;
+    use anyhow::Result;
+
+    #[test]
+    fn parse_shortcode_should_work() {
+        assert_eq!(parse_shortcode(":rust:"), Some("rust"));
+        assert_eq!(parse_shortcode(":+1:"), Some("+1"));
+        assert_eq!(parse_shortcode("👍"), None);
+        assert_eq!(parse_shortcode("::"), None);
+        assert_eq!(parse_shortcode(":no space:"), None);
+    }
+
+    #[tokio::test]
+    async fn reaction_add_and_remove_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state
+            .reaction_add(ReactionInput::new("👍"), 1, 1, 1)
+            .await?;
+        state
+            .reaction_add(ReactionInput::new("👍"), 1, 1, 2)
+            .await?;
+        // adding the same reaction twice is a no-op
+        state
+            .reaction_add(ReactionInput::new("👍"), 1, 1, 2)
+            .await?;
+        state
+            .reaction_add(ReactionInput::new(":rust:"), 1, 1, 2)
+            .await?;
+
+        let input = ListMessage {
+            last_id: None,
+            limit: 0,
+        };
+        let messages = state.list_message(input, 1, 1).await?;
+        let message = messages.iter().find(|m| m.id == 1).expect("message 1");
+        assert_eq!(
+            message.reactions,
+            vec![
+                ReactionSummary {
+                    emoji: "👍".to_string(),
+                    count: 2,
+                    reacted: true,
+                },
+                ReactionSummary {
+                    emoji: ":rust:".to_string(),
+                    count: 1,
+                    reacted: false,
+                },
+            ]
+        );
+
+        state
+            .reaction_remove(ReactionInput::new(":rust:"), 1, 1, 2)
+            .await?;
+        let err = state
+            .reaction_remove(ReactionInput::new(":rust:"), 1, 1, 2)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reaction_add_with_invalid_input_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state
+            .reaction_add(ReactionInput::new("1️⃣"), 1, 1, 1)
+            .await?;
+        state
+            .reaction_add(ReactionInput::new("👍🏽"), 1, 1, 1)
+            .await?;
+        // text isn't an emoji, even if it's not ascii
+        for emoji in ["like", "é", "好的", "👍👍", " 👍", ""] {
+            let err = state
+                .reaction_add(ReactionInput::new(emoji), 1, 1, 1)
+                .await
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("reaction error: Invalid emoji: {}", emoji)
+            );
+        }
+        // shortcodes must be custom emojis of the workspace
+        let err = state
+            .reaction_add(ReactionInput::new(":ferris:"), 1, 1, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "reaction error: Unknown custom emoji: :ferris:"
+        );
+
+        // message 1 is not in chat 2
+        let err = state
+            .reaction_add(ReactionInput::new("👍"), 2, 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+}
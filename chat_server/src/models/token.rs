@@ -0,0 +1,454 @@
+use crate::{AppError, AppState};
+use chat_core::{TokenId, TokenRevoked, User};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgConnection};
+use tracing::warn;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshToken {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Signout {
+    /// refresh token of another session to revoke with the rest of its family, the one of the
+    /// access token is always revoked
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
+/// A refresh token and the id of the access token to issue along with it
+#[derive(Debug, Clone)]
+pub struct SessionToken {
+    pub refresh_token: String,
+    pub access_jti: String,
+}
+
+#[derive(Debug, FromRow)]
+struct RefreshTokenRow {
+    id: i64,
+    user_id: i64,
+    ws_id: i64,
+    family: String,
+    expires_at: DateTime<Utc>,
+    used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl AppState {
+    /// Issue a refresh token of a new family, for a user just signed in to the workspace
+    pub async fn refresh_token_create(
+        &self,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<SessionToken, AppError> {
+        let mut conn = self.pool.acquire().await?;
+        let family = Uuid::new_v4().simple().to_string();
+        insert_refresh_token(&mut conn, user_id as _, ws_id as _, &family).await
+    }
+
+    /// Exchange the refresh token for its user in the workspace of the family, and the next token
+    /// of the family, each token is used once. A used token coming back is a stolen one, all tokens of its family are revoked.
+    pub async fn token_refresh(
+        &self,
+        input: &RefreshToken,
+    ) -> Result<(User, SessionToken), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let token: Option<RefreshTokenRow> = sqlx::query_as(
+            "
+            SELECT id, user_id, ws_id, family, expires_at, used_at, revoked_at
+            FROM refresh_tokens
+            WHERE token_hash = $1
+            FOR UPDATE
+            ",
+        )
+        .bind(hash_token(&input.refresh_token))
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(token) = token else {
+            return Err(AppError::RefreshTokenError(
+                "Invalid refresh token".to_string(),
+            ));
+        };
+        if token.revoked_at.is_some() {
+            return Err(AppError::RefreshTokenError(
+                "Refresh token has been revoked".to_string(),
+            ));
+        }
+        if token.used_at.is_some() {
+            warn!(
+                "refresh token {} of user {} reused, revoking family {}",
+                token.id, token.user_id, token.family
+            );
+            revoke_family(&mut tx, &token.family).await?;
+            tx.commit().await?;
+            return Err(AppError::RefreshTokenError(
+                "Refresh token has been used, all sessions of it are revoked".to_string(),
+            ));
+        }
+        if token.expires_at <= Utc::now() {
+            return Err(AppError::RefreshTokenError(
+                "Refresh token has expired".to_string(),
+            ));
+        }
+
+        // the token isn't used up if the user can't sign in any more
+        let Some(mut user) = self.find_user_by_id(token.user_id as _).await? else {
+            return Err(AppError::NotFound(format!("user id {}", token.user_id)));
+        };
+        if self
+            .get_workspace_role(token.ws_id as _, token.user_id as _)
+            .await?
+            .is_none()
+        {
+            return Err(AppError::PermissionDenied(format!(
+                "User {} is deactivated in workspace {}",
+                token.user_id, token.ws_id
+            )));
+        }
+        let Some(ws) = self.find_workspace_by_id(token.ws_id as _).await? else {
+            return Err(AppError::NotFound(format!("workspace id {}", token.ws_id)));
+        };
+        user.ws_id = ws.id;
+        user.ws_name = ws.name;
+
+        sqlx::query("UPDATE refresh_tokens SET used_at = NOW() WHERE id = $1")
+            .bind(token.id)
+            .execute(&mut *tx)
+            .await?;
+        let session =
+            insert_refresh_token(&mut tx, token.user_id, token.ws_id, &token.family).await?;
+        tx.commit().await?;
+        Ok((user, session))
+    }
+
+    /// Revoke the access token and the refresh tokens of its session, the other sessions stay
+    pub async fn signout(
+        &self,
+        user_id: u64,
+        token: &TokenId,
+        input: &Signout,
+    ) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        // revoked tokens are useless once expired
+        sqlx::query("DELETE FROM revoked_tokens WHERE expires_at <= NOW()")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "
+            INSERT INTO revoked_tokens (jti, user_id, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (jti) DO NOTHING
+            ",
+        )
+        .bind(&token.jti)
+        .bind(user_id as i64)
+        .bind(token.expires_at)
+        .execute(&mut *tx)
+        .await?;
+
+        let families: Vec<(String,)> = sqlx::query_as(
+            "
+            SELECT DISTINCT family
+            FROM refresh_tokens
+            WHERE user_id = $1 AND (access_jti = $2 OR token_hash = $3)
+            ",
+        )
+        .bind(user_id as i64)
+        .bind(&token.jti)
+        .bind(input.refresh_token.as_deref().map(hash_token))
+        .fetch_all(&mut *tx)
+        .await?;
+        for (family,) in families {
+            revoke_family(&mut tx, &family).await?;
+        }
+        tx.commit().await?;
+
+        // other instances are told by the database
+        self.revocations.apply(&TokenRevoked {
+            user_id: user_id as _,
+            jti: Some(token.jti.clone()),
+            expires_at: Some(token.expires_at),
+            revoked_before: None,
+        });
+        Ok(())
+    }
+
+    /// Revoke all access tokens issued to the user so far and all its refresh tokens
+    pub async fn signout_all(&self, user_id: u64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let (revoked_before,): (DateTime<Utc>,) = sqlx::query_as(
+            "UPDATE users SET tokens_revoked_at = NOW() WHERE id = $1 RETURNING tokens_revoked_at",
+        )
+        .bind(user_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query(
+            "
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL
+            ",
+        )
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.revocations.apply(&TokenRevoked {
+            user_id: user_id as _,
+            jti: None,
+            expires_at: None,
+            revoked_before: Some(revoked_before),
+        });
+        Ok(())
+    }
+}
+
+async fn insert_refresh_token(
+    conn: &mut PgConnection,
+    user_id: i64,
+    ws_id: i64,
+    family: &str,
+) -> Result<SessionToken, AppError> {
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let access_jti = Uuid::new_v4().simple().to_string();
+    sqlx::query(
+        "
+        INSERT INTO refresh_tokens (user_id, ws_id, family, token_hash, access_jti, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ",
+    )
+    .bind(user_id)
+    .bind(ws_id)
+    .bind(family)
+    .bind(hash_token(&token))
+    .bind(&access_jti)
+    .bind(Utc::now() + Duration::days(REFRESH_TOKEN_TTL_DAYS))
+    .execute(conn)
+    .await?;
+    Ok(SessionToken {
+        refresh_token: token,
+        access_jti,
+    })
+}
+
+async fn revoke_family(conn: &mut PgConnection, family: &str) -> Result<(), AppError> {
+    sqlx::query(
+        "
+        UPDATE refresh_tokens
+        SET revoked_at = NOW()
+        WHERE family = $1 AND revoked_at IS NULL
+        ",
+    )
+    .bind(family)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Only the hash of a token is stored, a leaked table doesn't leak the tokens
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use chat_core::{TokenRevocations, TokenVerify};
+
+    #[tokio::test]
+    async fn token_refresh_should_rotate() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let token = state.refresh_token_create(1, 1).await?.refresh_token;
+        assert_eq!(token.len(), 64);
+
+        let input = RefreshToken {
+            refresh_token: token,
+        };
+        let (user, next) = state.token_refresh(&input).await?;
+        assert_eq!(user.id, 1);
+        assert_eq!(user.ws_name, "acme");
+        assert_ne!(next.refresh_token, input.refresh_token);
+
+        let input = RefreshToken {
+            refresh_token: next.refresh_token,
+        };
+        let (user, _) = state.token_refresh(&input).await?;
+        assert_eq!(user.id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn token_refresh_should_stay_in_workspace_of_family() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = RefreshToken {
+            refresh_token: state.refresh_token_create(1, 1).await?.refresh_token,
+        };
+        // another sign in switches to workspace foo
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id) VALUES (2, 1)")
+            .execute(&state.pool)
+            .await?;
+        sqlx::query("UPDATE users SET ws_id = 2 WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        let (user, next) = state.token_refresh(&input).await?;
+        assert_eq!(user.ws_id, 1);
+        assert_eq!(user.ws_name, "acme");
+
+        sqlx::query(
+            "UPDATE workspace_members SET deactivated_at = NOW() WHERE ws_id = 1 AND user_id = 1",
+        )
+        .execute(&state.pool)
+        .await?;
+        let input = RefreshToken {
+            refresh_token: next.refresh_token,
+        };
+        let err = state.token_refresh(&input).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 1 is deactivated in workspace 1"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn token_reuse_should_revoke_family() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let stolen = RefreshToken {
+            refresh_token: state.refresh_token_create(1, 1).await?.refresh_token,
+        };
+        let other = RefreshToken {
+            refresh_token: state.refresh_token_create(1, 1).await?.refresh_token,
+        };
+        let (_, next) = state.token_refresh(&stolen).await?;
+
+        let err = state.token_refresh(&stolen).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "refresh token error: Refresh token has been used, all sessions of it are revoked"
+        );
+        let next = RefreshToken {
+            refresh_token: next.refresh_token,
+        };
+        let err = state.token_refresh(&next).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "refresh token error: Refresh token has been revoked"
+        );
+        // other sign ins are left alone
+        state.token_refresh(&other).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signout_should_revoke_session() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let session = state.refresh_token_create(1, 1).await?;
+        let token = state.ek.sign_with_id(user.clone(), &session.access_jti)?;
+        let other = state.ek.sign(user)?;
+        let (_, id) = state.verify(&token)?;
+        let input = Signout {
+            refresh_token: Some(state.refresh_token_create(1, 1).await?.refresh_token),
+        };
+        let refresh = RefreshToken {
+            refresh_token: session.refresh_token,
+        };
+        let session = RefreshToken {
+            refresh_token: state.refresh_token_create(1, 1).await?.refresh_token,
+        };
+
+        state.signout(1, &id, &input).await?;
+        let err = state.verify(&token).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: Token of user 1 has been revoked"
+        );
+        // the session of the access token and the one given are revoked
+        let err = state.token_refresh(&refresh).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "refresh token error: Refresh token has been revoked"
+        );
+        let refresh = RefreshToken {
+            refresh_token: input.refresh_token.unwrap(),
+        };
+        let err = state.token_refresh(&refresh).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "refresh token error: Refresh token has been revoked"
+        );
+        // other sessions stay
+        assert!(state.verify(&other).is_ok());
+        state.token_refresh(&session).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signout_all_should_revoke_all_sessions() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let token = state.ek.sign(user.clone())?;
+        let session = RefreshToken {
+            refresh_token: state.refresh_token_create(1, 1).await?.refresh_token,
+        };
+        let other = state.ek.sign(state.find_user_by_id(2).await?.unwrap())?;
+
+        state.signout_all(1).await?;
+        let err = state.verify(&token).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: Token of user 1 has been revoked"
+        );
+        let err = state.token_refresh(&session).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "refresh token error: Refresh token has been revoked"
+        );
+        assert!(state.verify(&other).is_ok());
+
+        // revocations are loaded by new instances
+        let revocations = TokenRevocations::default();
+        revocations.load(&state.pool).await?;
+        let (user, id) = state.dk.verify_with_id(&token)?;
+        assert!(revocations.is_revoked(user.id, &id));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_or_expired_token_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = RefreshToken {
+            refresh_token: "invalid".to_string(),
+        };
+        let err = state.token_refresh(&input).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "refresh token error: Invalid refresh token"
+        );
+
+        let input = RefreshToken {
+            refresh_token: state.refresh_token_create(1, 1).await?.refresh_token,
+        };
+        sqlx::query("UPDATE refresh_tokens SET expires_at = NOW()")
+            .execute(&state.pool)
+            .await?;
+        let err = state.token_refresh(&input).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "refresh token error: Refresh token has expired"
+        );
+        Ok(())
+    }
+}
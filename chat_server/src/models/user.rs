@@ -1,3 +1,10 @@
+use super::{
+    invitation::{accept_invitation, lock_invitation},
+    workspace::{
+        add_workspace_member, insert_workspace, lock_workspace_by_name, set_workspace_owner,
+    },
+    WorkspaceRole,
+};
 use crate::{AppError, AppState};
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
@@ -15,10 +22,14 @@ pub struct CreateUser {
     pub fullname: String,
     /// Email of user
     pub email: String,
-    /// Workspace name - if not exists, create one
+    /// Workspace name - if not exists, create one. Ignored when signing up with an invitation
+    #[serde(default)]
     pub workspace: String,
     /// Password of the user
     pub password: String,
+    /// Invitation token, required to join a workspace with an owner
+    #[serde(default)]
+    pub invite: Option<String>,
 }
 
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
@@ -48,10 +59,34 @@ impl AppState {
             return Err(AppError::EmailAlreadyExists(input.email.clone()));
         }
 
-        // check if workspace exists, if not create one
-        let ws = match self.find_workspace_by_name(&input.workspace).await? {
-            Some(ws) => ws,
-            None => self.workspace_create(&input.workspace, 0).await?,
+        let mut tx = self.pool.begin().await?;
+        // join the workspace of the invitation, or create the workspace if not exists
+        let (ws, role, invitation_id) = match input.invite.as_deref() {
+            Some(token) => {
+                let invitation = lock_invitation(&mut tx, token, &input.email).await?;
+                let Some(ws) = self.find_workspace_by_id(invitation.ws_id as _).await? else {
+                    return Err(AppError::NotFound(format!(
+                        "workspace id {}",
+                        invitation.ws_id
+                    )));
+                };
+                (ws, invitation.ws_role, Some(invitation.id))
+            }
+            // locked so that only one of concurrent first signups becomes the owner
+            None => match lock_workspace_by_name(&mut tx, &input.workspace).await? {
+                Some(ws) if ws.owner_id != 0 => {
+                    return Err(AppError::PermissionDenied(format!(
+                        "Workspace {} could only be joined by invitation",
+                        ws.name
+                    )));
+                }
+                Some(ws) => (ws, WorkspaceRole::Member, None),
+                None => (
+                    insert_workspace(&mut tx, &input.workspace, 0).await?,
+                    WorkspaceRole::Member,
+                    None,
+                ),
+            },
         };
 
         let password_hash = hash_password(&input.password)?;
@@ -66,16 +101,18 @@ impl AppState {
         .bind(&input.email)
         .bind(&input.fullname)
         .bind(password_hash)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
-
-        user.ws_name = ws.name.clone();
-
+        add_workspace_member(&mut tx, ws.id, user.id, role).await?;
+        if let Some(id) = invitation_id {
+            accept_invitation(&mut tx, id, user.id).await?;
+        }
         if ws.owner_id == 0 {
-            self.update_workspace_owner(ws.id as _, user.id as _)
-                .await?;
+            set_workspace_owner(&mut tx, ws.id, user.id).await?;
         }
+        tx.commit().await?;
 
+        user.ws_name = ws.name;
         Ok(user)
     }
 
@@ -104,9 +141,7 @@ impl AppState {
                 let is_valid =
                     verify_password(&input.password, &password_hash.unwrap_or_default())?;
                 if is_valid {
-                    // load ws_name, ws should exist
-                    let ws = self.find_workspace_by_id(user.ws_id as _).await?.unwrap();
-                    user.ws_name = ws.name;
+                    self.load_active_workspace(&mut user).await?;
                     Ok(Some(user))
                 } else {
                     Ok(None)
@@ -116,14 +151,48 @@ impl AppState {
         }
     }
 
-    pub async fn fetch_chat_user_by_ids(&self, ids: &[i64]) -> Result<Vec<ChatUser>, AppError> {
+    /// Sign in to the active workspace of the user, or another one if deactivated there
+    pub(crate) async fn load_active_workspace(&self, user: &mut User) -> Result<(), AppError> {
+        let ws_id: Option<(i64,)> = sqlx::query_as(
+            "
+            SELECT ws_id FROM workspace_members
+            WHERE user_id = $1 AND deactivated_at IS NULL
+            ORDER BY ws_id = $2 DESC, joined_at
+            LIMIT 1
+            ",
+        )
+        .bind(user.id)
+        .bind(user.ws_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some((ws_id,)) = ws_id else {
+            return Err(AppError::PermissionDenied(format!(
+                "User {} is deactivated",
+                user.id
+            )));
+        };
+        // load ws_name, ws should exist
+        let ws = self.find_workspace_by_id(ws_id as _).await?.unwrap();
+        user.ws_id = ws.id;
+        user.ws_name = ws.name;
+        Ok(())
+    }
+
+    /// Active users of the workspace with the ids, other users are left out
+    pub async fn fetch_chat_user_by_ids(
+        &self,
+        ws_id: u64,
+        ids: &[i64],
+    ) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             "
-            SELECT id, fullname, email
-            FROM users
-            WHERE id = ANY($1)
+            SELECT u.id, u.fullname, u.email
+            FROM users u
+            JOIN workspace_members m ON m.user_id = u.id
+            WHERE m.ws_id = $1 AND m.deactivated_at IS NULL AND u.id = ANY($2)
             ",
         )
+        .bind(ws_id as i64)
         .bind(ids)
         .fetch_all(&self.pool)
         .await?;
@@ -165,6 +234,7 @@ impl CreateUser {
             workspace: ws.to_string(),
             email: email.to_string(),
             password: password.to_string(),
+            invite: None,
         }
     }
 }
@@ -231,6 +301,25 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn concurrent_first_signups_should_have_one_owner() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        // workspace foo has no owner yet
+        let first = CreateUser::new("foo", "First Meng", "first@123.com", "hunter42");
+        let second = CreateUser::new("foo", "Second Meng", "second@123.com", "hunter42");
+        let (first, second) = tokio::join!(state.user_create(&first), state.user_create(&second));
+        let (user, err) = match (first, second) {
+            (Ok(user), Err(err)) | (Err(err), Ok(user)) => (user, err),
+            _ => panic!("Expecting only one signup to succeed"),
+        };
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        let ws = state.find_workspace_by_id(user.ws_id as _).await?.unwrap();
+        assert_eq!(ws.owner_id, user.id);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn find_user_by_id_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
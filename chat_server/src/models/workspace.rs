@@ -1,27 +1,215 @@
 use crate::{AppError, AppState};
-use chat_core::{ChatUser, Workspace};
+use chat_core::{ChatUser, MemberChanged, User, UserPresence, Workspace};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use utoipa::ToSchema;
+
+/// Role of a user in its workspace
+#[derive(
+    Debug, Clone, Copy, Default, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type,
+)]
+#[sqlx(type_name = "workspace_role", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum WorkspaceRole {
+    Owner,
+    Admin,
+    #[default]
+    Member,
+    /// only sees the chats it's added to
+    Guest,
+}
+
+/// A workspace the user is a member of
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct WorkspaceSummary {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub workspace: Workspace,
+    #[serde(alias = "wsRole")]
+    pub ws_role: WorkspaceRole,
+    #[serde(alias = "joinedAt")]
+    pub joined_at: DateTime<Utc>,
+}
+
+/// A member of the workspace, deactivated members can't use the workspace until reactivated
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct WorkspaceMember {
+    #[serde(alias = "wsId")]
+    pub ws_id: i64,
+    #[serde(alias = "userId")]
+    pub user_id: i64,
+    pub fullname: String,
+    pub email: String,
+    #[serde(alias = "wsRole")]
+    pub ws_role: WorkspaceRole,
+    #[serde(alias = "joinedAt")]
+    pub joined_at: DateTime<Utc>,
+    #[serde(alias = "deactivatedAt")]
+    pub deactivated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, Default)]
+pub struct UpdateWorkspaceMember {
+    /// admin, member or guest, the owner is changed by a transfer
+    #[serde(default)]
+    pub role: Option<WorkspaceRole>,
+    /// false to deactivate the member, true to reactivate it
+    #[serde(default)]
+    pub active: Option<bool>,
+}
+
+/// workspaces.name is VARCHAR(32)
+const MAX_WORKSPACE_NAME_LEN: usize = 32;
+/// workspaces.icon is VARCHAR(256)
+const MAX_WORKSPACE_ICON_LEN: usize = 256;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, Default)]
+pub struct UpdateWorkspace {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// url of the icon, an empty string removes it
+    #[serde(default)]
+    pub icon: Option<String>,
+    /// public channels of the workspace new members join
+    #[serde(default)]
+    pub default_channels: Option<Vec<i64>>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct TransferWorkspace {
+    /// the new owner, a member of the workspace
+    pub owner_id: i64,
+}
 
 impl AppState {
     pub async fn workspace_create(&self, name: &str, user_id: u64) -> Result<Workspace, AppError> {
+        let mut conn = self.pool.acquire().await?;
+        insert_workspace(&mut conn, name, user_id as _).await
+    }
+
+    /// Update the settings of the active workspace of the user, only the owner and admins could do it
+    pub async fn workspace_update(
+        &self,
+        input: UpdateWorkspace,
+        user: &User,
+    ) -> Result<Workspace, AppError> {
+        self.require_workspace_admin(user, "update").await?;
+        let name = input.name.map(|v| v.trim().to_string());
+        if let Some(name) = &name {
+            if name.is_empty() || name.chars().count() > MAX_WORKSPACE_NAME_LEN {
+                return Err(AppError::UpdateWorkspaceError(format!(
+                    "Workspace name must have 1 to {} characters",
+                    MAX_WORKSPACE_NAME_LEN
+                )));
+            }
+        }
+        if input
+            .icon
+            .as_ref()
+            .is_some_and(|v| v.chars().count() > MAX_WORKSPACE_ICON_LEN)
+        {
+            return Err(AppError::UpdateWorkspaceError(format!(
+                "Workspace icon must have at most {} characters",
+                MAX_WORKSPACE_ICON_LEN
+            )));
+        }
+        let default_channels = match input.default_channels {
+            Some(channels) => Some(
+                self.validate_default_channels(user.ws_id as _, channels)
+                    .await?,
+            ),
+            None => None,
+        };
+
         let ws = sqlx::query_as(
             r#"
-        INSERT INTO workspaces (name, owner_id)
-        VALUES ($1, $2)
-        RETURNING id, name, owner_id, created_at
+        UPDATE workspaces
+        SET name = COALESCE($2, name),
+            description = COALESCE($3, description),
+            icon = CASE WHEN $4::VARCHAR IS NULL THEN icon ELSE NULLIF($4, '') END,
+            default_channels = COALESCE($5, default_channels)
+        WHERE id = $1
+        RETURNING id, name, owner_id, description, icon, default_channels, created_at
         "#,
         )
-        .bind(name)
-        .bind(user_id as i64)
+        .bind(user.ws_id)
+        .bind(&name)
+        .bind(input.description)
+        .bind(input.icon)
+        .bind(default_channels)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| name_conflict(e, name.as_deref().unwrap_or_default()))?;
+        ws.ok_or_else(|| AppError::NotFound(format!("workspace id {}", user.ws_id)))
+    }
+
+    async fn validate_default_channels(
+        &self,
+        ws_id: u64,
+        mut channels: Vec<i64>,
+    ) -> Result<Vec<i64>, AppError> {
+        channels.sort();
+        channels.dedup();
+        let (count,): (i64,) = sqlx::query_as(
+            r#"
+        SELECT COUNT(*)
+        FROM chats
+        WHERE id = ANY($1) AND ws_id = $2 AND type = 'public_channel'
+        "#,
+        )
+        .bind(&channels)
+        .bind(ws_id as i64)
         .fetch_one(&self.pool)
         .await?;
+        if count as usize != channels.len() {
+            return Err(AppError::UpdateWorkspaceError(
+                "Default channels must be public channels of the workspace".to_string(),
+            ));
+        }
+        Ok(channels)
+    }
 
-        Ok(ws)
+    /// Transfer the active workspace of the user to another member, only the owner could do it
+    pub async fn workspace_transfer(
+        &self,
+        input: TransferWorkspace,
+        user: &User,
+    ) -> Result<Workspace, AppError> {
+        let Some(ws) = self.find_workspace_by_id(user.ws_id as _).await? else {
+            return Err(AppError::NotFound(format!("workspace id {}", user.ws_id)));
+        };
+        if ws.owner_id != user.id {
+            return Err(AppError::PermissionDenied(format!(
+                "User {} can't transfer workspace {}",
+                user.id, ws.id
+            )));
+        }
+        if input.owner_id == user.id {
+            return Ok(ws);
+        }
+        if self
+            .get_workspace_role(ws.id as _, input.owner_id as _)
+            .await?
+            .is_none()
+        {
+            return Err(AppError::NotFound(format!(
+                "member {} of workspace {}",
+                input.owner_id, ws.id
+            )));
+        }
+        self.update_workspace_owner(ws.id as _, input.owner_id as _)
+            .await
     }
 
     pub async fn find_workspace_by_name(&self, name: &str) -> Result<Option<Workspace>, AppError> {
         let ws = sqlx::query_as(
             r#"
-        SELECT id, name, owner_id, created_at
+        SELECT id, name, owner_id, description, icon, default_channels, created_at
         FROM workspaces
         WHERE name = $1
         "#,
@@ -37,7 +225,7 @@ impl AppState {
     pub async fn find_workspace_by_id(&self, id: u64) -> Result<Option<Workspace>, AppError> {
         let ws = sqlx::query_as(
             r#"
-        SELECT id, name, owner_id, created_at
+        SELECT id, name, owner_id, description, icon, default_channels, created_at
         FROM workspaces
         WHERE id = $1
         "#,
@@ -52,9 +240,10 @@ impl AppState {
     pub async fn fetch_all_chat_users(&self, id: u64) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-        SELECT id, fullname, email
-        FROM users
-        WHERE ws_id = $1 order by id
+        SELECT u.id, u.fullname, u.email
+        FROM users u
+        JOIN workspace_members m ON m.user_id = u.id
+        WHERE m.ws_id = $1 AND m.deactivated_at IS NULL order by u.id
         "#,
         )
         .bind(id as i64)
@@ -64,34 +253,454 @@ impl AppState {
         Ok(users)
     }
 
+    /// Presence of all users in the workspace over all notify server instances,
+    /// users never seen by notify server are offline
+    pub async fn fetch_user_presence(&self, id: u64) -> Result<Vec<UserPresence>, AppError> {
+        let presence = sqlx::query_as(
+            r#"
+        SELECT m.user_id, COALESCE(MIN(p.status), 'offline') AS status,
+            MAX(p.updated_at) AS updated_at
+        FROM workspace_members m
+        LEFT JOIN user_presence p ON p.user_id = m.user_id
+        WHERE m.ws_id = $1 AND m.deactivated_at IS NULL
+        GROUP BY m.user_id order by m.user_id
+        "#,
+        )
+        .bind(id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(presence)
+    }
+
     pub async fn update_workspace_owner(
         &self,
         id: u64,
         owner_id: u64,
     ) -> Result<Workspace, AppError> {
-        // update owner_id in two cases 1) owner_id = 0 2) owner's ws_id = id
-        let ws = sqlx::query_as(
+        let mut tx = self.pool.begin().await?;
+        let ws = set_workspace_owner(&mut tx, id as _, owner_id as _).await?;
+        tx.commit().await?;
+        Ok(ws)
+    }
+
+    /// Workspaces of the user, in the order joined
+    pub async fn list_workspaces(&self, user_id: u64) -> Result<Vec<WorkspaceSummary>, AppError> {
+        let workspaces = sqlx::query_as(
             r#"
-        UPDATE workspaces
-        SET owner_id = $1
-        WHERE id = $2 and (SELECT ws_id FROM users WHERE id = $1) = $2
-        RETURNING id, name, owner_id, created_at
+        SELECT w.id, w.name, w.owner_id, w.description, w.icon, w.default_channels, w.created_at,
+            m.ws_role, m.joined_at
+        FROM workspace_members m
+        JOIN workspaces w ON w.id = m.ws_id
+        WHERE m.user_id = $1 AND m.deactivated_at IS NULL
+        ORDER BY m.joined_at, w.id
         "#,
         )
-        .bind(owner_id as i64)
-        .bind(id as i64)
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(workspaces)
+    }
+
+    /// Role of the user in the workspace, None if not an active member
+    pub async fn get_workspace_role(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Option<WorkspaceRole>, AppError> {
+        let role: Option<(WorkspaceRole,)> = sqlx::query_as(
+            "
+            SELECT ws_role FROM workspace_members
+            WHERE ws_id = $1 AND user_id = $2 AND deactivated_at IS NULL
+            ",
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(role.map(|(role,)| role))
+    }
+
+    /// Only the owner and admins of the active workspace of the user could do the action
+    pub async fn require_workspace_admin(&self, user: &User, action: &str) -> Result<(), AppError> {
+        match self
+            .get_workspace_role(user.ws_id as _, user.id as _)
+            .await?
+        {
+            Some(WorkspaceRole::Owner | WorkspaceRole::Admin) => Ok(()),
+            _ => Err(AppError::PermissionDenied(format!(
+                "User {} can't {} workspace {}",
+                user.id, action, user.ws_id
+            ))),
+        }
+    }
+
+    /// Member of the workspace, deactivated or not
+    pub async fn get_workspace_member(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Option<WorkspaceMember>, AppError> {
+        let member = sqlx::query_as(
+            r#"
+        SELECT m.ws_id, m.user_id, u.fullname, u.email, m.ws_role, m.joined_at, m.deactivated_at
+        FROM workspace_members m
+        JOIN users u ON u.id = m.user_id
+        WHERE m.ws_id = $1 AND m.user_id = $2
+        "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(member)
+    }
+
+    /// Members of the active workspace of the user including deactivated ones, only the owner
+    /// and admins could list them
+    pub async fn list_workspace_members(
+        &self,
+        user: &User,
+    ) -> Result<Vec<WorkspaceMember>, AppError> {
+        self.require_workspace_admin(user, "list members of")
+            .await?;
+        let members = sqlx::query_as(
+            r#"
+        SELECT m.ws_id, m.user_id, u.fullname, u.email, m.ws_role, m.joined_at, m.deactivated_at
+        FROM workspace_members m
+        JOIN users u ON u.id = m.user_id
+        WHERE m.ws_id = $1
+        ORDER BY m.user_id
+        "#,
+        )
+        .bind(user.ws_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(members)
+    }
+
+    /// Change the role of a member of the active workspace of the user, or deactivate or
+    /// reactivate it. Admins manage members and guests, only the owner manages admins.
+    pub async fn workspace_member_update(
+        &self,
+        member_id: u64,
+        input: UpdateWorkspaceMember,
+        user: &User,
+    ) -> Result<WorkspaceMember, AppError> {
+        let member = self.require_member_admin(member_id, user, "update").await?;
+        if input.role == Some(WorkspaceRole::Owner) {
+            return Err(AppError::UpdateWorkspaceError(
+                "Workspace owner could only be changed by a transfer".to_string(),
+            ));
+        }
+        if input.role == Some(WorkspaceRole::Admin) {
+            self.require_workspace_owner(user, "assign admins of")
+                .await?;
+        }
+
+        let member: WorkspaceMember = sqlx::query_as(
+            r#"
+        WITH m AS (
+            UPDATE workspace_members
+            SET ws_role = COALESCE($3, ws_role),
+                deactivated_at = CASE
+                    WHEN $4::boolean IS NULL THEN deactivated_at
+                    WHEN $4 THEN NULL
+                    ELSE COALESCE(deactivated_at, NOW())
+                END
+            WHERE ws_id = $1 AND user_id = $2
+            RETURNING ws_id, user_id, ws_role, joined_at, deactivated_at
+        )
+        SELECT m.ws_id, m.user_id, u.fullname, u.email, m.ws_role, m.joined_at, m.deactivated_at
+        FROM m
+        JOIN users u ON u.id = m.user_id
+        "#,
+        )
+        .bind(member.ws_id)
+        .bind(member.user_id)
+        .bind(input.role)
+        .bind(input.active)
         .fetch_one(&self.pool)
         .await?;
 
-        Ok(ws)
+        self.set_member_deactivated(&member);
+        Ok(member)
     }
+
+    /// Remove a member from the active workspace of the user, it's deactivated and removed from
+    /// the chats of the workspace except its direct messages
+    pub async fn workspace_member_remove(
+        &self,
+        member_id: u64,
+        user: &User,
+    ) -> Result<(), AppError> {
+        let member = self.require_member_admin(member_id, user, "remove").await?;
+
+        let mut tx = self.pool.begin().await?;
+        let deactivated_at: (DateTime<Utc>,) = sqlx::query_as(
+            r#"
+        UPDATE workspace_members
+        SET deactivated_at = COALESCE(deactivated_at, NOW())
+        WHERE ws_id = $1 AND user_id = $2
+        RETURNING deactivated_at
+        "#,
+        )
+        .bind(member.ws_id)
+        .bind(member.user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        // the chats owned by the member are handed over to the workspace owner, the owner of a
+        // chat can't be removed from it
+        sqlx::query(
+            r#"
+        UPDATE chats c
+        SET owner_id = w.owner_id,
+            members = CASE WHEN w.owner_id = ANY(c.members) THEN c.members
+                ELSE array_append(c.members, w.owner_id)
+            END
+        FROM workspaces w
+        WHERE w.id = c.ws_id AND c.ws_id = $1 AND c.type <> 'single' AND c.owner_id = $2
+        "#,
+        )
+        .bind(member.ws_id)
+        .bind(member.user_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            r#"
+        UPDATE chats
+        SET members = array_remove(members, $2)
+        WHERE ws_id = $1 AND type <> 'single' AND $2 = ANY(members)
+        "#,
+        )
+        .bind(member.ws_id)
+        .bind(member.user_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.set_member_deactivated(&WorkspaceMember {
+            deactivated_at: Some(deactivated_at.0),
+            ..member
+        });
+        Ok(())
+    }
+
+    /// The member the user could manage, the owner and the user itself can't be managed
+    async fn require_member_admin(
+        &self,
+        member_id: u64,
+        user: &User,
+        action: &str,
+    ) -> Result<WorkspaceMember, AppError> {
+        self.require_workspace_admin(user, &format!("{} members of", action))
+            .await?;
+        let Some(member) = self
+            .get_workspace_member(user.ws_id as _, member_id)
+            .await?
+        else {
+            return Err(AppError::NotFound(format!(
+                "member {} of workspace {}",
+                member_id, user.ws_id
+            )));
+        };
+        if member.user_id == user.id {
+            return Err(AppError::PermissionDenied(format!(
+                "User {} can't {} itself",
+                user.id, action
+            )));
+        }
+        if member.ws_role == WorkspaceRole::Owner {
+            return Err(AppError::PermissionDenied(format!(
+                "User {} can't {} the owner of workspace {}",
+                user.id, action, user.ws_id
+            )));
+        }
+        if member.ws_role == WorkspaceRole::Admin {
+            self.require_workspace_owner(user, "manage admins of")
+                .await?;
+        }
+        Ok(member)
+    }
+
+    /// Only the owner of the active workspace of the user could do the action
+    pub(crate) async fn require_workspace_owner(
+        &self,
+        user: &User,
+        action: &str,
+    ) -> Result<(), AppError> {
+        match self
+            .get_workspace_role(user.ws_id as _, user.id as _)
+            .await?
+        {
+            Some(WorkspaceRole::Owner) => Ok(()),
+            _ => Err(AppError::PermissionDenied(format!(
+                "User {} can't {} workspace {}",
+                user.id, action, user.ws_id
+            ))),
+        }
+    }
+
+    /// Update the deactivated members right away, other instances are told by the database
+    fn set_member_deactivated(&self, member: &WorkspaceMember) {
+        self.deactivated.apply(&MemberChanged {
+            ws_id: member.ws_id,
+            user_id: member.user_id,
+            active: member.deactivated_at.is_none(),
+        });
+    }
+
+    /// Switch the active workspace of the user, it's also the one to sign in to next time
+    pub async fn workspace_switch(&self, id: u64, user: &User) -> Result<User, AppError> {
+        if self.get_workspace_role(id, user.id as _).await?.is_none() {
+            return Err(AppError::NotFound(format!("workspace id {}", id)));
+        }
+        let Some(ws) = self.find_workspace_by_id(id).await? else {
+            return Err(AppError::NotFound(format!("workspace id {}", id)));
+        };
+        sqlx::query("UPDATE users SET ws_id = $1 WHERE id = $2")
+            .bind(ws.id)
+            .bind(user.id)
+            .execute(&self.pool)
+            .await?;
+
+        let mut user = user.clone();
+        user.ws_id = ws.id;
+        user.ws_name = ws.name;
+        Ok(user)
+    }
+}
+
+/// A workspace name taken by another workspace is a conflict
+fn name_conflict(e: sqlx::Error, name: &str) -> AppError {
+    match &e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => {
+            AppError::WorkspaceNameAlreadyExists(name.to_string())
+        }
+        _ => e.into(),
+    }
+}
+
+pub(crate) async fn insert_workspace(
+    conn: &mut PgConnection,
+    name: &str,
+    owner_id: i64,
+) -> Result<Workspace, AppError> {
+    sqlx::query_as(
+        r#"
+        INSERT INTO workspaces (name, owner_id)
+        VALUES ($1, $2)
+        RETURNING id, name, owner_id, description, icon, default_channels, created_at
+        "#,
+    )
+    .bind(name)
+    .bind(owner_id)
+    .fetch_one(conn)
+    .await
+    .map_err(|e| name_conflict(e, name))
+}
+
+/// Find the workspace by name and lock it until the end of the transaction
+pub(crate) async fn lock_workspace_by_name(
+    conn: &mut PgConnection,
+    name: &str,
+) -> Result<Option<Workspace>, AppError> {
+    let ws = sqlx::query_as(
+        r#"
+        SELECT id, name, owner_id, description, icon, default_channels, created_at
+        FROM workspaces
+        WHERE name = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(name)
+    .fetch_optional(conn)
+    .await?;
+    Ok(ws)
+}
+
+/// Make the member the owner of the workspace, the previous owner stays as an admin
+pub(crate) async fn set_workspace_owner(
+    conn: &mut PgConnection,
+    id: i64,
+    owner_id: i64,
+) -> Result<Workspace, AppError> {
+    // update owner_id in two cases 1) owner_id = 0 2) owner is a member of the workspace
+    let ws = sqlx::query_as(
+        r#"
+        UPDATE workspaces
+        SET owner_id = $1
+        WHERE id = $2
+            AND EXISTS (
+                SELECT 1 FROM workspace_members
+                WHERE ws_id = $2 AND user_id = $1 AND deactivated_at IS NULL
+            )
+        RETURNING id, name, owner_id, description, icon, default_channels, created_at
+        "#,
+    )
+    .bind(owner_id)
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE workspace_members
+        SET ws_role = CASE WHEN user_id = $1 THEN 'owner'::workspace_role ELSE 'admin' END
+        WHERE ws_id = $2 AND (user_id = $1 OR ws_role = 'owner')
+        "#,
+    )
+    .bind(owner_id)
+    .bind(id)
+    .execute(conn)
+    .await?;
+
+    Ok(ws)
+}
+
+/// Add the user to the workspace, and to the default channels of the workspace
+pub(crate) async fn add_workspace_member(
+    conn: &mut PgConnection,
+    ws_id: i64,
+    user_id: i64,
+    role: WorkspaceRole,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO workspace_members (ws_id, user_id, ws_role)
+        VALUES ($1, $2, $3)
+        "#,
+    )
+    .bind(ws_id)
+    .bind(user_id)
+    .bind(role)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE chats
+        SET members = array_append(members, $2)
+        WHERE id = ANY(SELECT unnest(default_channels) FROM workspaces WHERE id = $1)
+            AND ws_id = $1 AND type = 'public_channel' AND NOT $2 = ANY(members)
+        "#,
+    )
+    .bind(ws_id)
+    .bind(user_id)
+    .execute(conn)
+    .await?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::CreateUser;
+    use crate::models::{AcceptInvitation, CreateChat, CreateInvitation, CreateUser, SigninUser};
     use anyhow::{Ok, Result};
+    use axum::{http::StatusCode, response::IntoResponse};
+    use chat_core::PresenceStatus;
+    use chat_core::TokenVerify;
 
     #[tokio::test]
     async fn workspace_should_create_and_set_owner() -> Result<()> {
@@ -109,6 +718,379 @@ mod tests {
             .update_workspace_owner(ws.id as _, user.id as _)
             .await?;
         assert_eq!(ws.owner_id, user.id);
+        let role = state.get_workspace_role(ws.id as _, user.id as _).await?;
+        assert_eq!(role, Some(WorkspaceRole::Owner));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_switch_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUser::new("zoo", "Zoo Meng", "zoo@123.com", "hunter42");
+        let owner = state.user_create(&input).await?;
+        let input = CreateInvitation {
+            email: Some("meng@123.com".to_string()),
+            ..Default::default()
+        };
+        let invitation = state.invitation_create(input, &owner).await?;
+
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = AcceptInvitation {
+            token: invitation.token.clone().unwrap(),
+        };
+        let ws = state.invitation_accept(input, &user).await?;
+        assert_eq!(ws.name, "zoo");
+        let workspaces = state.list_workspaces(1).await?;
+        let names: Vec<_> = workspaces
+            .iter()
+            .map(|v| v.workspace.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["acme", "zoo"]);
+        assert_eq!(workspaces[1].ws_role, WorkspaceRole::Member);
+
+        let user = state.workspace_switch(ws.id as _, &user).await?;
+        assert_eq!(user.ws_id, ws.id);
+        assert_eq!(user.ws_name, "zoo");
+        let users = state.fetch_all_chat_users(ws.id as _).await?;
+        assert_eq!(users.len(), 2);
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        assert_eq!(user.ws_id, ws.id);
+
+        // only users of the workspace could chat in it
+        let input = CreateChat::new("", &[1, 2], false);
+        let err = state.chat_create(input, 1, ws.id as _).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "create chat error: Some members do not exist"
+        );
+
+        let err = state.workspace_switch(3, &user).await.unwrap_err();
+        assert_eq!(err.to_string(), "not found: workspace id 3");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_update_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = UpdateWorkspace {
+            name: Some(" acme corp ".to_string()),
+            description: Some("Acme corporation".to_string()),
+            icon: Some("/files/1/abc/def/ghi.png".to_string()),
+            default_channels: Some(vec![1, 1]),
+        };
+        let ws = state.workspace_update(input, &owner).await?;
+        assert_eq!(ws.name, "acme corp");
+        assert_eq!(ws.description, "Acme corporation");
+        assert_eq!(ws.icon.as_deref(), Some("/files/1/abc/def/ghi.png"));
+        assert_eq!(ws.default_channels, vec![1]);
+
+        let input = UpdateWorkspace {
+            icon: Some("".to_string()),
+            ..Default::default()
+        };
+        let ws = state.workspace_update(input, &owner).await?;
+        assert_eq!(ws.name, "acme corp");
+        assert!(ws.icon.is_none());
+
+        // new members join the default channels
+        let invitation = state
+            .invitation_create(CreateInvitation::default(), &owner)
+            .await?;
+        let mut input = CreateUser::new("", "New Meng", "new@123.com", "hunter42");
+        input.invite = invitation.token;
+        let user = state.user_create(&input).await?;
+        let chat = state.get_chat_by_id(1).await?.expect("chat should exist");
+        assert!(chat.members.contains(&user.id));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_update_with_invalid_input_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = UpdateWorkspace {
+            name: Some("foo".to_string()),
+            ..Default::default()
+        };
+        let err = state.workspace_update(input, &owner).await.unwrap_err();
+        assert_eq!(err.to_string(), "workspace name already exists: foo");
+        assert_eq!(err.into_response().status(), StatusCode::CONFLICT);
+
+        let input = UpdateWorkspace {
+            name: Some("a".repeat(33)),
+            ..Default::default()
+        };
+        let err = state.workspace_update(input, &owner).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "update workspace error: Workspace name must have 1 to 32 characters"
+        );
+
+        let input = UpdateWorkspace {
+            icon: Some(format!("/files/1/{}.png", "a".repeat(256))),
+            ..Default::default()
+        };
+        let err = state.workspace_update(input, &owner).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "update workspace error: Workspace icon must have at most 256 characters"
+        );
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+
+        // chat 2 is a private channel
+        let input = UpdateWorkspace {
+            default_channels: Some(vec![1, 2]),
+            ..Default::default()
+        };
+        let err = state.workspace_update(input, &owner).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "update workspace error: Default channels must be public channels of the workspace"
+        );
+
+        let user = state.find_user_by_id(2).await?.expect("user should exist");
+        let err = state
+            .workspace_update(UpdateWorkspace::default(), &user)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 2 can't update workspace 1"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_transfer_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = TransferWorkspace { owner_id: 100 };
+        let err = state.workspace_transfer(input, &owner).await.unwrap_err();
+        assert_eq!(err.to_string(), "not found: member 100 of workspace 1");
+
+        let input = TransferWorkspace { owner_id: 2 };
+        let ws = state.workspace_transfer(input, &owner).await?;
+        assert_eq!(ws.owner_id, 2);
+        assert_eq!(
+            state.get_workspace_role(1, 2).await?,
+            Some(WorkspaceRole::Owner)
+        );
+        assert_eq!(
+            state.get_workspace_role(1, 1).await?,
+            Some(WorkspaceRole::Admin)
+        );
+
+        let input = TransferWorkspace { owner_id: 3 };
+        let err = state.workspace_transfer(input, &owner).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 1 can't transfer workspace 1"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_member_deactivate_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        let user = state.find_user_by_id(3).await?.expect("user should exist");
+        let token = state.ek.sign(user.clone())?;
+        assert!(state.verify(&token).is_ok());
+
+        let input = UpdateWorkspaceMember {
+            active: Some(false),
+            ..Default::default()
+        };
+        let member = state.workspace_member_update(3, input, &owner).await?;
+        assert!(member.deactivated_at.is_some());
+        assert_eq!(member.ws_role, WorkspaceRole::Member);
+        let err = state.verify(&token).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 3 is deactivated in workspace 1"
+        );
+        assert_eq!(state.get_workspace_role(1, 3).await?, None);
+        let users = state.fetch_all_chat_users(1).await?;
+        assert!(users.iter().all(|v| v.id != 3));
+        let signin = SigninUser::new(&user.email, "123456");
+        let err = state.user_verify(&signin).await.unwrap_err();
+        assert_eq!(err.to_string(), "permission denied: User 3 is deactivated");
+
+        // deactivated members are still listed to admins
+        let members = state.list_workspace_members(&owner).await?;
+        assert_eq!(members.len(), 5);
+        assert!(members
+            .iter()
+            .any(|v| v.user_id == 3 && v.deactivated_at.is_some()));
+
+        let input = UpdateWorkspaceMember {
+            role: Some(WorkspaceRole::Guest),
+            active: Some(true),
+        };
+        let member = state.workspace_member_update(3, input, &owner).await?;
+        assert!(member.deactivated_at.is_none());
+        assert_eq!(member.ws_role, WorkspaceRole::Guest);
+        assert!(state.verify(&token).is_ok());
+        assert!(state.user_verify(&signin).await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_member_update_should_check_role() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        let admin = state.find_user_by_id(2).await?.expect("user should exist");
+        let member = state.find_user_by_id(3).await?.expect("user should exist");
+
+        let err = state.list_workspace_members(&member).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 3 can't list members of workspace 1"
+        );
+
+        let input = UpdateWorkspaceMember {
+            role: Some(WorkspaceRole::Admin),
+            ..Default::default()
+        };
+        state.workspace_member_update(2, input, &owner).await?;
+
+        let input = UpdateWorkspaceMember {
+            role: Some(WorkspaceRole::Admin),
+            ..Default::default()
+        };
+        let err = state
+            .workspace_member_update(3, input, &admin)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 2 can't assign admins of workspace 1"
+        );
+
+        let input = UpdateWorkspaceMember {
+            role: Some(WorkspaceRole::Owner),
+            ..Default::default()
+        };
+        let err = state
+            .workspace_member_update(3, input, &owner)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "update workspace error: Workspace owner could only be changed by a transfer"
+        );
+
+        let input = UpdateWorkspaceMember {
+            active: Some(false),
+            ..Default::default()
+        };
+        let err = state
+            .workspace_member_update(1, input.clone(), &admin)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 2 can't update the owner of workspace 1"
+        );
+        let err = state
+            .workspace_member_update(2, input.clone(), &admin)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 2 can't update itself"
+        );
+        let err = state
+            .workspace_member_update(100, input.clone(), &admin)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "not found: member 100 of workspace 1");
+
+        // admins manage members, only the owner manages admins
+        state
+            .workspace_member_update(3, input.clone(), &admin)
+            .await?;
+        state.workspace_member_update(2, input, &owner).await?;
+        assert_eq!(state.get_workspace_role(1, 2).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_member_remove_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        state.workspace_member_remove(2, &owner).await?;
+
+        assert_eq!(state.get_workspace_role(1, 2).await?, None);
+        assert!(state.deactivated.is_deactivated(1, 2));
+        // removed from the channels and groups, direct messages are kept
+        let chats = state.fetch_chats(Default::default(), 2, 1).await?;
+        assert_eq!(chats.len(), 1);
+        assert_eq!(chats[0].chat.id, 3);
+
+        // a deactivated member can't accept invitations to come back
+        let invitation = state
+            .invitation_create(CreateInvitation::default(), &owner)
+            .await?;
+        let user = state.find_user_by_id(2).await?.expect("user should exist");
+        let input = AcceptInvitation {
+            token: invitation.token.unwrap(),
+        };
+        let err = state.invitation_accept(input, &user).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invitation error: User 2 is deactivated in workspace 1"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_member_remove_should_hand_over_chats() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = CreateChat::new("lobby", &[2, 3], true);
+        let lobby = state.chat_create(input, 2, 1).await?;
+        state.workspace_member_remove(2, &owner).await?;
+
+        let lobby = state
+            .get_chat_by_id(lobby.id as _)
+            .await?
+            .expect("chat should exist");
+        assert_eq!(lobby.owner_id, 1);
+        assert_eq!(lobby.members, vec![3, 1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn guest_should_not_join_channels() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = UpdateWorkspaceMember {
+            role: Some(WorkspaceRole::Guest),
+            ..Default::default()
+        };
+        state.workspace_member_update(2, input, &owner).await?;
+
+        let input = CreateChat::new("lobby", &[1, 3], true);
+        let lobby = state.chat_create(input, 1, 1).await?;
+        let channels = state.fetch_channels(Default::default(), 2, 1).await?;
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].chat.id, 1);
+
+        let err = state.chat_join(lobby.id as _, 2, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: Guest 2 can't join channels of workspace 1"
+        );
         Ok(())
     }
 
@@ -128,4 +1110,20 @@ mod tests {
         assert_eq!(users.len(), 5);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn workspace_should_fetch_user_presence() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query("INSERT INTO user_presence (user_id, ws_id, status) VALUES (2, 1, 'away')")
+            .execute(&state.pool)
+            .await?;
+
+        let presence = state.fetch_user_presence(1).await?;
+        assert_eq!(presence.len(), 5);
+        assert_eq!(presence[0].status, PresenceStatus::Offline);
+        assert!(presence[0].updated_at.is_none());
+        assert_eq!(presence[1].user_id, 2);
+        assert_eq!(presence[1].status, PresenceStatus::Away);
+        Ok(())
+    }
 }
@@ -1,9 +1,16 @@
 use crate::{
-    handlers::*, AppState, AuthOutput, CreateChat, CreateMessage, CreateUser, ErrorOutput,
-    ListMessage, SigninUser,
+    handlers::*, AcceptInvitation, AppState, AuthOutput, ChannelSummary, ChatMember, ChatPin,
+    ChatRole, ChatSummary, CreateChat, CreateInvitation, CreateMessage, CreateUser, ErrorOutput,
+    Invitation, ListChannels, ListChats, ListMessage, MarkRead, ReactionInput, RefreshToken,
+    SearchMessage, SearchResult, SigninUser, Signout, TransferWorkspace, UpdateChat,
+    UpdateChatMember, UpdateMemberSettings, UpdateMessage, UpdateWorkspace, UpdateWorkspaceMember,
+    WorkspaceMember, WorkspaceRole, WorkspaceSummary,
 };
 use axum::Router;
-use chat_core::{Chat, ChatType, ChatUser, Message, User, Workspace};
+use chat_core::{
+    Chat, ChatRead, ChatType, ChatUser, Message, MessageReaction, MessageRevision, PresenceStatus,
+    ReactionSummary, User, UserPresence, Workspace,
+};
 use utoipa::{
     openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
     Modify, OpenApi,
@@ -21,15 +28,56 @@ pub(crate) trait OpenApiRouter {
         paths(
             signup_handler,
             signin_handler,
+            refresh_token_handler,
+            signout_handler,
+            signout_all_handler,
             list_chat_handler,
             create_chat_handler,
+            open_single_chat_handler,
+            list_channel_handler,
+            join_chat_handler,
+            leave_chat_handler,
+            list_chat_members_handler,
+            update_chat_member_handler,
+            update_chat_settings_handler,
             get_chat_handler,
+            update_chat_handler,
+            delete_chat_handler,
+            mark_chat_read_handler,
+            typing_handler,
             list_message_handler,
+            list_thread_handler,
+            update_message_handler,
+            delete_message_handler,
+            list_message_revisions_handler,
+            add_reaction_handler,
+            remove_reaction_handler,
+            list_pins_handler,
+            pin_message_handler,
+            unpin_message_handler,
+            search_messages_handler,
+            list_user_presence_handler,
+            list_invitations_handler,
+            create_invitation_handler,
+            revoke_invitation_handler,
+            accept_invitation_handler,
+            list_workspaces_handler,
+            switch_workspace_handler,
+            get_workspace_handler,
+            update_workspace_handler,
+            transfer_workspace_handler,
+            list_workspace_members_handler,
+            update_workspace_member_handler,
+            remove_workspace_member_handler,
         ),
         components(
-            schemas(User, Chat, ChatType, ChatUser, Message, Workspace, SigninUser,
-                CreateUser, CreateChat, CreateMessage, ListMessage,
-                AuthOutput, ErrorOutput),
+            schemas(User, Chat, ChatSummary, ChannelSummary, ChatRead, ChatType, ChatUser, Message, MessageRevision, MessageReaction,
+                ReactionSummary, UserPresence, PresenceStatus, Workspace, SigninUser, CreateUser, CreateChat, UpdateChat,
+                CreateMessage, UpdateMessage, ListChats, ListChannels, ListMessage, ReactionInput, SearchMessage,
+                SearchResult, MarkRead, ChatMember, ChatRole, ChatPin, UpdateChatMember, UpdateMemberSettings,
+                Invitation, CreateInvitation, AcceptInvitation, WorkspaceRole, WorkspaceSummary,
+                UpdateWorkspace, TransferWorkspace, WorkspaceMember, UpdateWorkspaceMember,
+                AuthOutput, RefreshToken, Signout, ErrorOutput),
         ),
         modifiers(&SecurityAddon),
         tags(
@@ -1,7 +1,8 @@
 use anyhow::Result;
-use chat_core::{Chat, ChatType, Message};
+use chat_core::{Chat, ChatRead, ChatType, Message, MessageReaction, PresenceStatus, UserPresence};
 use chat_server::AppState;
-use futures::StreamExt;
+use futures::{SinkExt, StreamExt};
+use notify_server::{HealthOutput, MetricsOutput};
 use reqwest::{
     multipart::{Form, Part},
     Client, StatusCode,
@@ -9,10 +10,17 @@ use reqwest::{
 use reqwest_eventsource::{Event, EventSource};
 use serde::Deserialize;
 use serde_json::json;
+use sqlx::PgPool;
 use std::{net::SocketAddr, time::Duration};
-use tokio::{net::TcpListener, time::sleep};
+use tokio::{
+    net::TcpListener,
+    time::{sleep, timeout},
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
 
 const WILD_ADDR: &str = "0.0.0.0:0";
+/// well beyond the 8000 bytes limit of a Postgres notification payload
+const LARGE_MESSAGE_SIZE: usize = 20 * 1024;
 
 #[derive(Debug, Deserialize)]
 struct AuthToken {
@@ -25,67 +33,315 @@ struct ChatServer {
     client: Client,
 }
 
-struct NotifyServer;
+struct NotifyServer {
+    addr: SocketAddr,
+    metrics_addr: SocketAddr,
+    token: String,
+}
 
 #[tokio::test]
 async fn chat_server_should_work() -> Result<()> {
     let (tdb, state) = AppState::new_for_test().await?;
     let chat_server = ChatServer::new(state).await?;
     let db_url = tdb.url();
-    let _ = NotifyServer::new(&db_url, &chat_server.token).await?;
+    let notify_server = NotifyServer::new(&db_url, &chat_server.token, "test").await?;
     let chat = chat_server.create_chat().await?;
-    let _message = chat_server.create_message(chat.id as _).await?;
+    chat_server.open_dm().await?;
+    let message = chat_server.create_message(chat.id as _).await?;
+    sleep(Duration::from_secs(1)).await;
+    chat_server.presence().await?;
+    chat_server
+        .reply_message(chat.id as _, message.id as _)
+        .await?;
+    sleep(Duration::from_secs(1)).await;
+    chat_server
+        .react_message(chat.id as _, message.id as _)
+        .await?;
+    sleep(Duration::from_secs(1)).await;
+    chat_server.mark_read(chat.id as _, message.id as _).await?;
+    sleep(Duration::from_secs(1)).await;
+    chat_server.typing(chat.id as _).await?;
     sleep(Duration::from_secs(1)).await;
+    chat_server
+        .update_message(chat.id as _, message.id as _)
+        .await?;
+    sleep(Duration::from_secs(1)).await;
+    chat_server
+        .delete_message(chat.id as _, message.id as _)
+        .await?;
+    sleep(Duration::from_secs(1)).await;
+    chat_server.delete_chat(chat.id as _).await?;
+    sleep(Duration::from_secs(1)).await;
+    notify_server.replay().await?;
+    notify_server.metrics().await?;
+    chat_server.large_message(&notify_server).await?;
+    let other_server = NotifyServer::start(&db_url, &chat_server.token, "test2").await?;
+    other_server.resync().await?;
+    notify_server.reconnect(&tdb.get_pool().await).await?;
+    chat_server.signout(&notify_server).await?;
     Ok(())
 }
 
 impl NotifyServer {
-    async fn new(db_url: &str, token: &str) -> Result<Self> {
+    async fn new(db_url: &str, token: &str, instance_id: &str) -> Result<Self> {
+        let server = Self::start(db_url, token, instance_id).await?;
+        let (addr, token) = (server.addr, &server.token);
+
+        let mut es = EventSource::get(format!("http://{}/events?token={}", addr, token));
+
+        tokio::spawn(async move {
+            while let Some(event) = es.next().await {
+                match event {
+                    Ok(Event::Open) => println!("Connection Open!"),
+                    Ok(Event::Message(message)) => check_event(&message.event, &message.data),
+                    Err(err) => {
+                        println!("Error: {}", err);
+                        es.close();
+                    }
+                }
+            }
+        });
+
+        let (mut ws, _) = connect_async(format!("ws://{}/ws?token={}", addr, token)).await?;
+        ws.send(WsMessage::Text(r#"{"cmd": "ping"}"#.to_string()))
+            .await?;
+        let Some(WsMessage::Text(pong)) = ws.next().await.transpose()? else {
+            panic!("expect a pong frame");
+        };
+        assert_eq!(pong, r#"{"event":"Pong"}"#);
+        ws.send(WsMessage::Text(
+            r#"{"cmd": "typing", "chat_id": 100}"#.to_string(),
+        ))
+        .await?;
+        let Some(WsMessage::Text(error)) = ws.next().await.transpose()? else {
+            panic!("expect an error frame");
+        };
+        assert_eq!(error, r#"{"event":"Error","error":"chat 100 not found"}"#);
+
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = ws.next().await {
+                if let WsMessage::Text(data) = message {
+                    let event: serde_json::Value = serde_json::from_str(&data).unwrap();
+                    check_event(event["event"].as_str().unwrap(), &data);
+                }
+            }
+        });
+
+        Ok(server)
+    }
+
+    async fn start(db_url: &str, token: &str, instance_id: &str) -> Result<Self> {
         let mut config = notify_server::AppConfig::load()?;
         config.server.db_url = db_url.to_string();
-        let app = notify_server::get_router(config).await?;
+        config.server.instance_id = instance_id.to_string();
+        let (app, metrics) = notify_server::get_router(config).await?;
         let listener = TcpListener::bind(WILD_ADDR).await?;
         let addr = listener.local_addr()?;
+        let metrics_listener = TcpListener::bind(WILD_ADDR).await?;
+        let metrics_addr = metrics_listener.local_addr()?;
 
         tokio::spawn(async move {
             axum::serve(listener, app.into_make_service())
                 .await
                 .unwrap();
         });
+        tokio::spawn(async move {
+            axum::serve(metrics_listener, metrics.into_make_service())
+                .await
+                .unwrap();
+        });
 
-        let mut es = EventSource::get(format!("http://{}/events?token={}", addr, token));
+        Ok(NotifyServer {
+            addr,
+            metrics_addr,
+            token: token.to_string(),
+        })
+    }
 
-        tokio::spawn(async move {
+    // reconnect from the very beginning, all events of the flow should be replayed
+    async fn replay(&self) -> Result<()> {
+        let req = Client::new()
+            .get(format!("http://{}/events?token={}", self.addr, self.token))
+            .header("Last-Event-ID", "test-0");
+        let mut es = EventSource::new(req)?;
+        let mut names = vec![];
+        let mut last_id = 0;
+        let replay = async {
             while let Some(event) = es.next().await {
-                match event {
-                    Ok(Event::Open) => println!("Connection Open!"),
-                    Ok(Event::Message(message)) => match message.event.as_ref() {
-                        "NewChat" => {
-                            let chat: Chat = serde_json::from_str(&message.data).unwrap();
-                            assert_eq!(chat.name.as_ref().unwrap(), "test");
-                            assert_eq!(chat.members, vec![1, 2]);
-                            assert_eq!(chat.r#type, ChatType::PrivateChannel);
-                        }
-
-                        "NewMessage" => {
-                            let msg: Message = serde_json::from_str(&message.data).unwrap();
-                            assert_eq!(msg.content, "hello");
-                            assert_eq!(msg.files.len(), 1);
-                            assert_eq!(msg.sender_id, 1);
-                        }
-                        _ => {
-                            panic!("unexpected event: {:?}", message);
-                        }
-                    },
-                    Err(err) => {
-                        println!("Error: {}", err);
-                        es.close();
-                    }
+                let Event::Message(message) = event? else {
+                    continue;
+                };
+                check_event(&message.event, &message.data);
+                let (instance_id, id) = message.id.rsplit_once('-').unwrap();
+                assert_eq!(instance_id, "test");
+                let id: u64 = id.parse()?;
+                assert!(id > last_id);
+                last_id = id;
+                names.push(message.event);
+                if names.last().unwrap() == "RemoveFromChat" {
+                    break;
                 }
             }
-        });
+            Ok::<_, anyhow::Error>(())
+        };
+        timeout(Duration::from_secs(5), replay).await??;
+        es.close();
+        assert_eq!(
+            names,
+            vec![
+                "NewChat",
+                "NewMessage",
+                "NewThreadReply",
+                "ReactionAdded",
+                "ReactionRemoved",
+                "ReadReceipt",
+                "MessageUpdated",
+                "MessageDeleted",
+                "RemoveFromChat",
+            ]
+        );
+        Ok(())
+    }
+
+    async fn metrics(&self) -> Result<()> {
+        // not exposed with the app, even to signed in users
+        let res = Client::new()
+            .get(format!("http://{}/metrics?token={}", self.addr, self.token))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let res = Client::new()
+            .get(format!("http://{}/metrics", self.metrics_addr))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let metrics: MetricsOutput = res.json().await?;
+        assert_eq!(metrics.instance_id, "test");
+        // the SSE and websocket listeners, replay connection may be closing
+        assert!(metrics.connections >= 2);
+        assert!(metrics.notifications >= 9);
+        assert!(metrics.events_sent >= 9);
+        Ok(())
+    }
+
+    // the listener of notifications reconnects after its connection is killed, clients are told to resync
+    async fn reconnect(&self, pool: &PgPool) -> Result<()> {
+        let mut es = EventSource::get(format!("http://{}/events?token={}", self.addr, self.token));
+        let Some(Ok(Event::Open)) = es.next().await else {
+            panic!("expect the connection open");
+        };
+
+        sqlx::query(
+            "
+            SELECT pg_terminate_backend(pid)
+            FROM pg_stat_activity
+            WHERE datname = current_database() AND query LIKE 'LISTEN%'
+            ",
+        )
+        .execute(pool)
+        .await?;
+
+        let resync = async {
+            while let Some(event) = es.next().await {
+                if let Event::Message(message) = event? {
+                    return Ok::<_, anyhow::Error>(message.event);
+                }
+            }
+            Ok(String::new())
+        };
+        let name = timeout(Duration::from_secs(5), resync).await??;
+        es.close();
+        assert_eq!(name, "Resync");
+
+        let res = Client::new()
+            .get(format!("http://{}/health", self.addr))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let health: HealthOutput = res.json().await?;
+        assert!(health.listener.connected);
+        assert_eq!(health.listener.reconnects, 1);
+        assert!(health.listener.last_error.is_some());
+        Ok(())
+    }
 
-        Ok(NotifyServer)
+    // ids of another instance can't be resumed, the client has to resync
+    async fn resync(&self) -> Result<()> {
+        let req = Client::new()
+            .get(format!("http://{}/events?token={}", self.addr, self.token))
+            .header("Last-Event-ID", "test-0");
+        let mut es = EventSource::new(req)?;
+        let resync = async {
+            while let Some(event) = es.next().await {
+                if let Event::Message(message) = event? {
+                    return Ok::<_, anyhow::Error>(message.event);
+                }
+            }
+            Ok(String::new())
+        };
+        let name = timeout(Duration::from_secs(5), resync).await??;
+        es.close();
+        assert_eq!(name, "Resync");
+        Ok(())
+    }
+}
+
+// events are the same on both SSE and websocket
+fn check_event(name: &str, data: &str) {
+    match name {
+        "NewChat" | "RemoveFromChat" => {
+            let chat: Chat = serde_json::from_str(data).unwrap();
+            assert_eq!(chat.name.as_ref().unwrap(), "test");
+            assert_eq!(chat.members, vec![1, 2]);
+            assert_eq!(chat.r#type, ChatType::PrivateChannel);
+        }
+
+        "NewMessage" => {
+            let msg: Message = serde_json::from_str(data).unwrap();
+            assert_eq!(msg.sender_id, 1);
+            if msg.content.len() == LARGE_MESSAGE_SIZE {
+                assert!(msg.files.is_empty());
+            } else {
+                assert_eq!(msg.content, "hello");
+                assert_eq!(msg.files.len(), 1);
+            }
+        }
+
+        "NewThreadReply" => {
+            let msg: Message = serde_json::from_str(data).unwrap();
+            assert_eq!(msg.content, "hello thread");
+            assert!(msg.thread_root_id.is_some());
+        }
+
+        "ReactionAdded" | "ReactionRemoved" => {
+            let reaction: MessageReaction = serde_json::from_str(data).unwrap();
+            assert_eq!(reaction.emoji, "👍");
+            assert_eq!(reaction.user_id, 1);
+        }
+
+        "ReadReceipt" => {
+            let read: ChatRead = serde_json::from_str(data).unwrap();
+            assert_eq!(read.user_id, 1);
+        }
+
+        "MessageUpdated" => {
+            let msg: Message = serde_json::from_str(data).unwrap();
+            assert_eq!(msg.content, "hello again");
+            assert_eq!(msg.files.len(), 1);
+            assert!(msg.edited_at.is_some());
+        }
+
+        "MessageDeleted" => {
+            let msg: Message = serde_json::from_str(data).unwrap();
+            assert_eq!(msg.content, "");
+            assert!(msg.deleted_at.is_some());
+        }
+
+        "Resync" => {}
+        _ => {
+            panic!("unexpected event {}: {:?}", name, data);
+        }
     }
 }
 
@@ -128,6 +384,62 @@ impl ChatServer {
         Ok(ret.token)
     }
 
+    // signing out closes the SSE stream of the token, and the token is rejected by both servers
+    async fn signout(&self, notify_server: &NotifyServer) -> Result<()> {
+        let token = self.signin().await?;
+        let mut es = EventSource::get(format!(
+            "http://{}/events?token={}",
+            notify_server.addr, token
+        ));
+        let Some(Ok(Event::Open)) = es.next().await else {
+            panic!("expect the connection open");
+        };
+
+        let res = self
+            .client
+            .post(format!("http://{}/api/signout", self.addr))
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        let closed = async {
+            while let Some(event) = es.next().await {
+                if event.is_err() {
+                    return;
+                }
+            }
+        };
+        timeout(Duration::from_secs(5), closed).await?;
+        es.close();
+
+        let res = Client::new()
+            .get(format!(
+                "http://{}/events?token={}",
+                notify_server.addr, token
+            ))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = self
+            .client
+            .get(format!("http://{}/api/chats", self.addr))
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // other sessions stay
+        let res = self
+            .client
+            .get(format!("http://{}/api/chats", self.addr))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
+
     async fn create_chat(&self) -> Result<Chat> {
         let res = self
             .client
@@ -145,6 +457,21 @@ impl ChatServer {
         Ok(chat)
     }
 
+    // the single chat with user 2 exists already, no event is sent
+    async fn open_dm(&self) -> Result<()> {
+        let res = self
+            .client
+            .get(format!("http://{}/api/chats/dm/2", self.addr))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let chat: Chat = res.json().await?;
+        assert_eq!(chat.id, 3);
+        assert_eq!(chat.r#type, ChatType::Single);
+        Ok(())
+    }
+
     async fn create_message(&self, chat_id: u64) -> Result<Message> {
         // upload file
         let data = include_bytes!("../Cargo.toml");
@@ -182,4 +509,205 @@ impl ChatServer {
         assert_eq!(message.chat_id, chat_id as i64);
         Ok(message)
     }
+
+    // the notification carries ids only, so a message over the payload limit still arrives
+    async fn large_message(&self, notify_server: &NotifyServer) -> Result<()> {
+        let mut es = EventSource::get(format!(
+            "http://{}/events?token={}",
+            notify_server.addr, self.token
+        ));
+        let Some(Ok(Event::Open)) = es.next().await else {
+            panic!("expect the connection open");
+        };
+
+        let content = "x".repeat(LARGE_MESSAGE_SIZE);
+        let body = serde_json::to_string(&json!({ "content": content }))?;
+        let res = self
+            .client
+            .post(format!("http://{}/api/chats/1", self.addr))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let message: Message = res.json().await?;
+
+        let received = async {
+            while let Some(event) = es.next().await {
+                if let Event::Message(event) = event? {
+                    if event.event == "NewMessage" {
+                        return Ok::<_, anyhow::Error>(serde_json::from_str(&event.data)?);
+                    }
+                }
+            }
+            anyhow::bail!("the stream ended");
+        };
+        let received: Message = timeout(Duration::from_secs(5), received).await??;
+        es.close();
+        assert_eq!(received.id, message.id);
+        assert_eq!(received.content, content);
+        Ok(())
+    }
+
+    async fn reply_message(&self, chat_id: u64, msg_id: u64) -> Result<Message> {
+        let body = serde_json::to_string(&json!({
+            "content": "hello thread",
+            "reply_to": msg_id,
+        }))?;
+        let res = self
+            .client
+            .post(format!("http://{}/api/chats/{}", self.addr, chat_id))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Content-Type", "application/json")
+            .body(body);
+        let res = res.send().await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let message: Message = res.json().await?;
+        assert_eq!(message.reply_to, Some(msg_id as i64));
+        assert_eq!(message.thread_root_id, Some(msg_id as i64));
+
+        let res = self
+            .client
+            .get(format!(
+                "http://{}/api/chats/{}/messages/{}/thread",
+                self.addr, chat_id, msg_id
+            ))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let replies: Vec<Message> = res.json().await?;
+        assert_eq!(replies, vec![message.clone()]);
+        Ok(message)
+    }
+
+    async fn react_message(&self, chat_id: u64, msg_id: u64) -> Result<()> {
+        let url = format!(
+            "http://{}/api/chats/{}/messages/{}/reactions",
+            self.addr, chat_id, msg_id
+        );
+        let body = r#"{"emoji": "👍"}"#;
+        let res = self
+            .client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let res = self
+            .client
+            .get(format!(
+                "http://{}/api/chats/{}/messages",
+                self.addr, chat_id
+            ))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?;
+        let messages: Vec<Message> = res.json().await?;
+        let message = messages.iter().find(|m| m.id == msg_id as i64).unwrap();
+        assert_eq!(message.reactions.len(), 1);
+        assert_eq!(message.reactions[0].count, 1);
+        assert!(message.reactions[0].reacted);
+
+        let res = self
+            .client
+            .delete(&url)
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        Ok(())
+    }
+
+    async fn mark_read(&self, chat_id: u64, msg_id: u64) -> Result<ChatRead> {
+        let body = serde_json::to_string(&json!({ "message_id": msg_id }))?;
+        let res = self
+            .client
+            .post(format!("http://{}/api/chats/{}/read", self.addr, chat_id))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let read: ChatRead = res.json().await?;
+        assert_eq!(read.chat_id, chat_id as i64);
+        assert_eq!(read.last_read_message_id, msg_id as i64);
+        Ok(read)
+    }
+
+    async fn presence(&self) -> Result<()> {
+        let res = self
+            .client
+            .get(format!("http://{}/api/users/presence", self.addr))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let presence: Vec<UserPresence> = res.json().await?;
+        // user 1 is connected to notify server
+        let user = presence.iter().find(|p| p.user_id == 1).unwrap();
+        assert_eq!(user.status, PresenceStatus::Online);
+        Ok(())
+    }
+
+    async fn typing(&self, chat_id: u64) -> Result<()> {
+        let res = self
+            .client
+            .post(format!("http://{}/api/chats/{}/typing", self.addr, chat_id))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        Ok(())
+    }
+
+    async fn update_message(&self, chat_id: u64, msg_id: u64) -> Result<Message> {
+        let res = self
+            .client
+            .patch(format!(
+                "http://{}/api/chats/{}/messages/{}",
+                self.addr, chat_id, msg_id
+            ))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Content-Type", "application/json")
+            .body(r#"{"content": "hello again"}"#);
+        let res = res.send().await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let message: Message = res.json().await?;
+        assert_eq!(message.content, "hello again");
+        assert!(message.edited_at.is_some());
+        Ok(message)
+    }
+
+    async fn delete_message(&self, chat_id: u64, msg_id: u64) -> Result<()> {
+        let res = self
+            .client
+            .delete(format!(
+                "http://{}/api/chats/{}/messages/{}",
+                self.addr, chat_id, msg_id
+            ))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        Ok(())
+    }
+
+    async fn delete_chat(&self, chat_id: u64) -> Result<()> {
+        let res = self
+            .client
+            .delete(format!("http://{}/api/chats/{}", self.addr, chat_id))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        Ok(())
+    }
 }
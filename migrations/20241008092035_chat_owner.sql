@@ -0,0 +1,18 @@
+-- Add migration script here
+-- add owner for chats, which is the user who created the chat
+ALTER TABLE
+  chats
+ADD
+  COLUMN owner_id BIGINT NOT NULL DEFAULT 0 REFERENCES users(id);
+
+-- existing chats are owned by their first member
+UPDATE
+  chats
+SET
+  owner_id = members [1];
+
+-- delete messages along with the chat they belong to
+ALTER TABLE
+  messages DROP CONSTRAINT messages_chat_id_fkey,
+ADD
+  CONSTRAINT messages_chat_id_fkey FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE;
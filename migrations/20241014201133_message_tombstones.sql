@@ -0,0 +1,37 @@
+-- Add migration script here
+-- deleted messages are kept as tombstones so that pagination by id stays stable
+ALTER TABLE
+  messages
+ADD
+  COLUMN deleted_at TIMESTAMPTZ;
+
+-- if new message added, edited or deleted, notify with message data
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  SELECT
+    members INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', NEW;
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('message', NEW, 'members', USERS)::text);
+  ELSIF TG_OP = 'UPDATE' AND NEW.deleted_at IS DISTINCT FROM OLD.deleted_at THEN
+    RAISE NOTICE 'delete_message: %', NEW;
+    PERFORM
+      pg_notify('chat_message_deleted', json_build_object('message', NEW, 'members', USERS)::text);
+  ELSIF TG_OP = 'UPDATE' AND NEW.edited_at IS DISTINCT FROM OLD.edited_at THEN
+    RAISE NOTICE 'update_message: %', NEW;
+    PERFORM
+      pg_notify('chat_message_updated', json_build_object('message', NEW, 'members', USERS)::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,15 @@
+-- Add migration script here
+-- threaded replies: reply_to is the message replied to, thread_root_id is the first message of the thread
+ALTER TABLE
+  messages
+ADD
+  COLUMN reply_to BIGINT REFERENCES messages(id) ON DELETE SET NULL,
+ADD
+  COLUMN thread_root_id BIGINT REFERENCES messages(id) ON DELETE CASCADE,
+ADD
+  COLUMN reply_count INT NOT NULL DEFAULT 0;
+
+-- create index for messages for thread_root_id
+CREATE INDEX IF NOT EXISTS thread_root_id_index ON messages(thread_root_id, id DESC)
+WHERE
+  thread_root_id IS NOT NULL;
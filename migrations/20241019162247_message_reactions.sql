@@ -0,0 +1,48 @@
+-- Add migration script here
+-- emoji reactions on messages, emoji is either a unicode emoji or a custom emoji shortcode like :rust:
+CREATE TABLE IF NOT EXISTS message_reactions (
+  message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  user_id BIGINT NOT NULL REFERENCES users(id),
+  emoji VARCHAR(64) NOT NULL,
+  created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (message_id, user_id, emoji)
+);
+
+-- if reaction added or removed, notify with reaction data
+CREATE OR REPLACE FUNCTION add_to_reaction()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  REACTION message_reactions;
+  CHAT bigint;
+  USERS bigint[];
+BEGIN
+  IF TG_OP = 'DELETE' THEN
+    REACTION := OLD;
+  ELSE
+    REACTION := NEW;
+  END IF;
+  SELECT
+    c.id,
+    c.members INTO CHAT,
+    USERS
+  FROM
+    messages m
+    JOIN chats c ON c.id = m.chat_id
+  WHERE
+    m.id = REACTION.message_id;
+  -- the chat may be gone already when reactions are removed by cascade
+  IF USERS IS NOT NULL THEN
+    RAISE NOTICE 'add_to_reaction: %', REACTION;
+    PERFORM
+      pg_notify('message_reaction_changed', json_build_object('op', TG_OP, 'reaction', json_build_object('message_id', REACTION.message_id, 'chat_id', CHAT, 'user_id', REACTION.user_id, 'emoji', REACTION.emoji), 'members', USERS)::text);
+  END IF;
+  RETURN REACTION;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_reaction_trigger
+  AFTER INSERT OR DELETE ON message_reactions
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_reaction();
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- full-text search over message content
+ALTER TABLE
+  messages
+ADD
+  COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED;
+
+-- create index for messages for search_vector
+CREATE INDEX IF NOT EXISTS message_search_index ON messages USING GIN(search_vector);
@@ -0,0 +1,37 @@
+-- Add migration script here
+-- read position of each user in each chat
+CREATE TABLE IF NOT EXISTS chat_reads (
+  chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id BIGINT NOT NULL REFERENCES users(id),
+  last_read_message_id BIGINT NOT NULL,
+  updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chat_id, user_id)
+);
+
+-- if read position moved, notify with read data
+CREATE OR REPLACE FUNCTION add_to_chat_read()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  IF TG_OP = 'INSERT' OR NEW.last_read_message_id IS DISTINCT FROM OLD.last_read_message_id THEN
+    RAISE NOTICE 'add_to_chat_read: %', NEW;
+    SELECT
+      members INTO USERS
+    FROM
+      chats
+    WHERE
+      id = NEW.chat_id;
+    PERFORM
+      pg_notify('chat_read_updated', json_build_object('read', NEW, 'members', USERS)::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_chat_read_trigger
+  AFTER INSERT OR UPDATE ON chat_reads
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_chat_read();
@@ -0,0 +1,41 @@
+-- Add migration script here
+-- last message and last activity of a chat, maintained when a message is created
+ALTER TABLE chats
+  ADD COLUMN last_message_id BIGINT,
+  ADD COLUMN last_activity_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+UPDATE
+  chats c
+SET
+  last_message_id = m.id,
+  last_activity_at = m.created_at
+FROM (
+  SELECT DISTINCT ON (chat_id)
+    chat_id,
+    id,
+    created_at
+  FROM
+    messages
+  ORDER BY
+    chat_id,
+    id DESC) m
+WHERE
+  m.chat_id = c.id;
+
+UPDATE
+  chats
+SET
+  last_activity_at = COALESCE(created_at, CURRENT_TIMESTAMP)
+WHERE
+  last_message_id IS NULL;
+
+-- create index for chat list ordered by activity
+CREATE INDEX IF NOT EXISTS ws_id_last_activity_at_index ON chats(ws_id, last_activity_at DESC, id DESC);
+
+-- activity changes shouldn't notify, only changes of the chat itself
+DROP TRIGGER IF EXISTS add_to_chat_trigger ON chats;
+
+CREATE TRIGGER add_to_chat_trigger
+  AFTER INSERT OR UPDATE OF ws_id, name, type, members, owner_id OR DELETE ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_chat();
@@ -0,0 +1,42 @@
+-- Add migration script here
+CREATE TYPE presence_status AS ENUM (
+  'online',
+  'away',
+  'offline'
+);
+
+-- presence of each user, maintained by notify_server from its SSE connections
+CREATE TABLE IF NOT EXISTS user_presence (
+  user_id BIGINT PRIMARY KEY REFERENCES users(id),
+  ws_id BIGINT NOT NULL REFERENCES workspaces(id),
+  status presence_status NOT NULL,
+  updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- if presence status changed, notify the users of the workspace
+CREATE OR REPLACE FUNCTION add_to_user_presence()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  IF TG_OP = 'INSERT' OR NEW.status IS DISTINCT FROM OLD.status THEN
+    RAISE NOTICE 'add_to_user_presence: %', NEW;
+    SELECT
+      array_agg(id) INTO USERS
+    FROM
+      users
+    WHERE
+      ws_id = NEW.ws_id;
+    PERFORM
+      pg_notify('user_presence_changed', json_build_object('presence', NEW, 'members', USERS)::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_user_presence_trigger
+  AFTER INSERT OR UPDATE ON user_presence
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_user_presence();
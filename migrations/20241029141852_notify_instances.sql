@@ -0,0 +1,56 @@
+-- Add migration script here
+-- presence is kept by each notify server instance, the status of a user is the best of all instances
+ALTER TABLE user_presence
+  ADD COLUMN instance_id TEXT NOT NULL DEFAULT '';
+
+ALTER TABLE user_presence
+  DROP CONSTRAINT user_presence_pkey;
+
+ALTER TABLE user_presence
+  ADD PRIMARY KEY (user_id, instance_id);
+
+-- notify server instances with their last heartbeat
+CREATE TABLE IF NOT EXISTS notify_instances (
+  instance_id TEXT PRIMARY KEY,
+  seen_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- if the status of a user over all instances changed, notify the users of the workspace
+CREATE OR REPLACE FUNCTION add_to_user_presence()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+  OTHERS presence_status;
+  OLD_STATUS presence_status;
+  NEW_STATUS presence_status;
+BEGIN
+  -- online < away < offline, the least one wins
+  SELECT
+    MIN(status) INTO OTHERS
+  FROM
+    user_presence
+  WHERE
+    user_id = NEW.user_id
+    AND instance_id <> NEW.instance_id;
+  IF TG_OP = 'INSERT' THEN
+    OLD_STATUS := COALESCE(OTHERS, 'offline');
+  ELSE
+    OLD_STATUS := LEAST(OTHERS, OLD.status);
+  END IF;
+  NEW_STATUS := LEAST(OTHERS, NEW.status);
+  IF NEW_STATUS IS DISTINCT FROM OLD_STATUS THEN
+    RAISE NOTICE 'add_to_user_presence: %', NEW;
+    SELECT
+      array_agg(id) INTO USERS
+    FROM
+      users
+    WHERE
+      ws_id = NEW.ws_id;
+    PERFORM
+      pg_notify('user_presence_changed', json_build_object('presence', json_build_object('user_id', NEW.user_id, 'status', NEW_STATUS, 'updated_at', NEW.updated_at), 'members', USERS)::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,150 @@
+-- Add migration script here
+-- pg_notify payloads are limited to 8000 bytes, notifications only carry ids and notify server loads the rows
+-- deleted chats can't be loaded anymore, they're kept here for a while
+CREATE TABLE IF NOT EXISTS deleted_chats (
+  id BIGINT PRIMARY KEY,
+  ws_id BIGINT NOT NULL,
+  name VARCHAR(64),
+  type chat_type NOT NULL,
+  members BIGINT[] NOT NULL,
+  owner_id BIGINT NOT NULL,
+  created_at TIMESTAMPTZ,
+  deleted_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- if chat changed, notify with chat id, and the members removed by an update
+CREATE OR REPLACE FUNCTION add_to_chat()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_chat: %', NEW.id;
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', TG_OP, 'chat_id', NEW.id)::text);
+  ELSIF TG_OP = 'UPDATE' THEN
+    RAISE NOTICE 'add_to_chat: %', NEW.id;
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', TG_OP, 'chat_id', NEW.id, 'members_changed', OLD.members IS DISTINCT FROM NEW.members, 'removed', ARRAY (
+            SELECT
+              unnest(OLD.members)
+            EXCEPT
+            SELECT
+              unnest(NEW.members)))::text);
+  ELSE
+    RAISE NOTICE 'add_to_chat: %', OLD.id;
+    INSERT INTO deleted_chats(id, ws_id, name, type, members, owner_id, created_at)
+      VALUES (OLD.id, OLD.ws_id, OLD.name, OLD.type, OLD.members, OLD.owner_id, OLD.created_at)
+    ON CONFLICT (id)
+      DO NOTHING;
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', TG_OP, 'chat_id', OLD.id)::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+-- if new message added, edited or deleted, notify with message id
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  PAYLOAD text;
+BEGIN
+  PAYLOAD := json_build_object('message_id', NEW.id, 'chat_id', NEW.chat_id)::text;
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', NEW.id;
+    PERFORM
+      pg_notify('chat_message_created', PAYLOAD);
+  ELSIF TG_OP = 'UPDATE' AND NEW.deleted_at IS DISTINCT FROM OLD.deleted_at THEN
+    RAISE NOTICE 'delete_message: %', NEW.id;
+    PERFORM
+      pg_notify('chat_message_deleted', PAYLOAD);
+  ELSIF TG_OP = 'UPDATE' AND NEW.edited_at IS DISTINCT FROM OLD.edited_at THEN
+    RAISE NOTICE 'update_message: %', NEW.id;
+    PERFORM
+      pg_notify('chat_message_updated', PAYLOAD);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+-- if reaction added or removed, notify with reaction data
+CREATE OR REPLACE FUNCTION add_to_reaction()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  REACTION message_reactions;
+  CHAT bigint;
+BEGIN
+  IF TG_OP = 'DELETE' THEN
+    REACTION := OLD;
+  ELSE
+    REACTION := NEW;
+  END IF;
+  SELECT
+    c.id INTO CHAT
+  FROM
+    messages m
+    JOIN chats c ON c.id = m.chat_id
+  WHERE
+    m.id = REACTION.message_id;
+  -- the chat may be gone already when reactions are removed by cascade
+  IF CHAT IS NOT NULL THEN
+    RAISE NOTICE 'add_to_reaction: %', REACTION;
+    PERFORM
+      pg_notify('message_reaction_changed', json_build_object('op', TG_OP, 'reaction', json_build_object('message_id', REACTION.message_id, 'chat_id', CHAT, 'user_id', REACTION.user_id, 'emoji', REACTION.emoji))::text);
+  END IF;
+  RETURN REACTION;
+END;
+$$
+LANGUAGE plpgsql;
+
+-- if read position moved, notify with read data
+CREATE OR REPLACE FUNCTION add_to_chat_read()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' OR NEW.last_read_message_id IS DISTINCT FROM OLD.last_read_message_id THEN
+    RAISE NOTICE 'add_to_chat_read: %', NEW;
+    PERFORM
+      pg_notify('chat_read_updated', json_build_object('read', NEW)::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+-- if the status of a user over all instances changed, notify with the workspace of the user
+CREATE OR REPLACE FUNCTION add_to_user_presence()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  OTHERS presence_status;
+  OLD_STATUS presence_status;
+  NEW_STATUS presence_status;
+BEGIN
+  -- online < away < offline, the least one wins
+  SELECT
+    MIN(status) INTO OTHERS
+  FROM
+    user_presence
+  WHERE
+    user_id = NEW.user_id
+    AND instance_id <> NEW.instance_id;
+  IF TG_OP = 'INSERT' THEN
+    OLD_STATUS := COALESCE(OTHERS, 'offline');
+  ELSE
+    OLD_STATUS := LEAST(OTHERS, OLD.status);
+  END IF;
+  NEW_STATUS := LEAST(OTHERS, NEW.status);
+  IF NEW_STATUS IS DISTINCT FROM OLD_STATUS THEN
+    RAISE NOTICE 'add_to_user_presence: %', NEW;
+    PERFORM
+      pg_notify('user_presence_changed', json_build_object('presence', json_build_object('user_id', NEW.user_id, 'status', NEW_STATUS, 'updated_at', NEW.updated_at), 'ws_id', NEW.ws_id)::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,69 @@
+-- Add migration script here
+-- a pair of users has only one single chat, members of single chats are kept sorted
+UPDATE
+  chats
+SET
+  members = ARRAY (
+    SELECT
+      unnest(members)
+    ORDER BY
+      1)
+WHERE
+  type = 'single';
+
+-- single chats created more than once are merged into the oldest one
+CREATE TEMPORARY TABLE single_chat_dups AS
+SELECT
+  id,
+  keep_id
+FROM (
+  SELECT
+    id,
+    MIN(id) OVER (PARTITION BY ws_id, members) AS keep_id
+  FROM
+    chats
+  WHERE
+    type = 'single') c
+WHERE
+  id <> keep_id;
+
+UPDATE
+  messages m
+SET
+  chat_id = d.keep_id
+FROM
+  single_chat_dups d
+WHERE
+  m.chat_id = d.id;
+
+DELETE FROM chats c USING single_chat_dups d
+WHERE c.id = d.id;
+
+UPDATE
+  chats c
+SET
+  last_message_id = m.id,
+  last_activity_at = m.created_at
+FROM (
+  SELECT DISTINCT ON (chat_id)
+    chat_id,
+    id,
+    created_at
+  FROM
+    messages
+  ORDER BY
+    chat_id,
+    id DESC) m
+WHERE
+  m.chat_id = c.id
+  AND c.id IN (
+    SELECT
+      keep_id
+    FROM
+      single_chat_dups);
+
+DROP TABLE single_chat_dups;
+
+CREATE UNIQUE INDEX IF NOT EXISTS single_chat_members_index ON chats(ws_id, members)
+WHERE
+  type = 'single';
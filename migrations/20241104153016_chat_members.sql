@@ -0,0 +1,81 @@
+-- Add migration script here
+-- role of a member in a chat
+CREATE TYPE chat_role AS ENUM(
+  'owner',
+  'admin',
+  'member'
+);
+
+-- members of chats with their role and settings, chats.members stays the list of members
+CREATE TABLE IF NOT EXISTS chat_members (
+  chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id BIGINT NOT NULL REFERENCES users(id),
+  role chat_role NOT NULL DEFAULT 'member',
+  joined_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  -- no notification for new messages of the chat
+  muted BOOLEAN NOT NULL DEFAULT FALSE,
+  PRIMARY KEY (chat_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS chat_members_user_id_index ON chat_members(user_id);
+
+INSERT INTO chat_members(chat_id, user_id, role, joined_at)
+SELECT
+  c.id,
+  m.user_id,
+  CASE WHEN m.user_id = c.owner_id THEN
+    'owner'::chat_role
+  ELSE
+    'member'::chat_role
+  END,
+  COALESCE(c.created_at, CURRENT_TIMESTAMP)
+FROM
+  chats c,
+  unnest(c.members) AS m(user_id)
+ON CONFLICT (chat_id, user_id)
+  DO NOTHING;
+
+-- members added to or removed from chats.members are added to or removed from chat_members
+CREATE OR REPLACE FUNCTION sync_chat_members()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  DELETE FROM chat_members
+  WHERE chat_id = NEW.id
+    AND NOT user_id = ANY (NEW.members);
+  INSERT INTO chat_members(chat_id, user_id, role)
+  SELECT
+    NEW.id,
+    m.user_id,
+    CASE WHEN m.user_id = NEW.owner_id THEN
+      'owner'::chat_role
+    ELSE
+      'member'::chat_role
+    END
+  FROM
+    unnest(NEW.members) AS m(user_id)
+  ON CONFLICT (chat_id, user_id)
+    DO NOTHING;
+  IF TG_OP = 'UPDATE' AND NEW.owner_id IS DISTINCT FROM OLD.owner_id THEN
+    UPDATE
+      chat_members
+    SET
+      role = CASE WHEN user_id = NEW.owner_id THEN
+        'owner'::chat_role
+      ELSE
+        'admin'::chat_role
+      END
+    WHERE
+      chat_id = NEW.id
+      AND (user_id = NEW.owner_id
+        OR role = 'owner');
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER sync_chat_members_trigger
+  AFTER INSERT OR UPDATE OF members, owner_id ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION sync_chat_members();
@@ -0,0 +1,36 @@
+-- Add migration script here
+-- role of a user in its workspace
+CREATE TYPE workspace_role AS ENUM(
+  'owner',
+  'admin',
+  'member'
+);
+
+ALTER TABLE users
+  ADD COLUMN ws_role workspace_role NOT NULL DEFAULT 'member';
+
+UPDATE
+  users u
+SET
+  ws_role = 'owner'
+FROM
+  workspaces w
+WHERE
+  w.owner_id = u.id;
+
+-- invitations to join a workspace, workspaces with an owner could only be joined by invitation
+CREATE TABLE IF NOT EXISTS workspace_invitations (
+  id BIGSERIAL PRIMARY KEY,
+  ws_id BIGINT NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+  token VARCHAR(64) NOT NULL UNIQUE,
+  -- only this email could accept the invitation, anyone with the token if null
+  email VARCHAR(64),
+  ws_role workspace_role NOT NULL DEFAULT 'member',
+  created_by BIGINT NOT NULL REFERENCES users(id),
+  expires_at TIMESTAMPTZ NOT NULL,
+  accepted_by BIGINT REFERENCES users(id),
+  accepted_at TIMESTAMPTZ,
+  created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS workspace_invitations_ws_id_index ON workspace_invitations(ws_id);
@@ -0,0 +1,27 @@
+-- Add migration script here
+-- users could be members of several workspaces, users.ws_id is the workspace they sign in to
+CREATE TABLE IF NOT EXISTS workspace_members (
+  ws_id BIGINT NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+  user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  ws_role workspace_role NOT NULL DEFAULT 'member',
+  joined_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (ws_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS workspace_members_user_id_index ON workspace_members(user_id);
+
+INSERT INTO workspace_members(ws_id, user_id, ws_role, joined_at)
+SELECT
+  u.ws_id,
+  u.id,
+  u.ws_role,
+  COALESCE(u.created_at, CURRENT_TIMESTAMP)
+FROM
+  users u
+  JOIN workspaces w ON w.id = u.ws_id
+ON CONFLICT (ws_id, user_id)
+  DO NOTHING;
+
+-- the role is kept per workspace now
+ALTER TABLE users
+  DROP COLUMN ws_role;
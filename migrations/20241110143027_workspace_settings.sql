@@ -0,0 +1,6 @@
+-- Add migration script here
+-- settings of workspaces, new members join the default channels
+ALTER TABLE workspaces
+  ADD COLUMN description TEXT NOT NULL DEFAULT '',
+  ADD COLUMN icon VARCHAR(256),
+  ADD COLUMN default_channels BIGINT[] NOT NULL DEFAULT '{}';
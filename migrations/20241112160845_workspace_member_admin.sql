@@ -0,0 +1,27 @@
+-- Add migration script here
+-- guests only see the chats they're added to
+ALTER TYPE workspace_role ADD VALUE IF NOT EXISTS 'guest';
+
+-- deactivated members can't use the workspace until reactivated, their tokens are rejected
+ALTER TABLE workspace_members
+  ADD COLUMN deactivated_at TIMESTAMPTZ;
+
+-- if a member is deactivated or reactivated, notify servers to block or unblock its tokens
+CREATE OR REPLACE FUNCTION add_to_workspace_member()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF (NEW.deactivated_at IS NULL) IS DISTINCT FROM (OLD.deactivated_at IS NULL) THEN
+    RAISE NOTICE 'add_to_workspace_member: %', NEW;
+    PERFORM
+      pg_notify('workspace_member_changed', json_build_object('ws_id', NEW.ws_id, 'user_id', NEW.user_id, 'active', NEW.deactivated_at IS NULL)::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_workspace_member_trigger
+  AFTER UPDATE OF deactivated_at ON workspace_members
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_workspace_member();
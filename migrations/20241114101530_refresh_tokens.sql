@@ -0,0 +1,19 @@
+-- Add migration script here
+-- refresh tokens to renew short lived access tokens, each one is used once and replaced by a new
+-- one of the same family. A used token coming back means it's stolen, the family is revoked.
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  -- tokens rotated from the same sign in
+  family VARCHAR(32) NOT NULL,
+  -- sha256 of the token, the token itself is never stored
+  token_hash CHAR(64) NOT NULL UNIQUE,
+  expires_at TIMESTAMPTZ NOT NULL,
+  used_at TIMESTAMPTZ,
+  revoked_at TIMESTAMPTZ,
+  created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_index ON refresh_tokens(family);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_id_index ON refresh_tokens(user_id);
@@ -0,0 +1,52 @@
+-- Add migration script here
+-- access tokens revoked by signing out, kept until they expire
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+  jti VARCHAR(32) PRIMARY KEY,
+  user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  expires_at TIMESTAMPTZ NOT NULL,
+  revoked_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS revoked_tokens_expires_at_index ON revoked_tokens(expires_at);
+
+-- tokens of the user issued before are revoked, by signing out all sessions
+ALTER TABLE users
+  ADD COLUMN tokens_revoked_at TIMESTAMPTZ;
+
+-- if a token is revoked, notify servers to reject it and close the streams using it
+CREATE OR REPLACE FUNCTION add_to_revoked_token()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  RAISE NOTICE 'add_to_revoked_token: %', NEW;
+  PERFORM
+    pg_notify('token_revoked', json_build_object('user_id', NEW.user_id, 'jti', NEW.jti, 'expires_at', NEW.expires_at)::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_revoked_token_trigger
+  AFTER INSERT ON revoked_tokens
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_revoked_token();
+
+-- if all sessions of a user are signed out, notify servers the same way
+CREATE OR REPLACE FUNCTION add_to_user_tokens_revoked()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF NEW.tokens_revoked_at IS DISTINCT FROM OLD.tokens_revoked_at THEN
+    RAISE NOTICE 'add_to_user_tokens_revoked: %', NEW.id;
+    PERFORM
+      pg_notify('token_revoked', json_build_object('user_id', NEW.id, 'revoked_before', NEW.tokens_revoked_at)::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_user_tokens_revoked_trigger
+  AFTER UPDATE OF tokens_revoked_at ON users
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_user_tokens_revoked();
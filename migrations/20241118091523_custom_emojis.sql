@@ -0,0 +1,10 @@
+-- Add migration script here
+-- custom emojis of a workspace, members react with them as :shortcode:
+CREATE TABLE IF NOT EXISTS custom_emojis (
+  ws_id BIGINT NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+  shortcode VARCHAR(32) NOT NULL,
+  url VARCHAR(256) NOT NULL,
+  created_by BIGINT NOT NULL REFERENCES users(id),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (ws_id, shortcode)
+);
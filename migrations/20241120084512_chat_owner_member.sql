@@ -0,0 +1,18 @@
+-- Add migration script here
+-- the owner of a chat is always a member of it, so its chat_members row is never removed
+CREATE OR REPLACE FUNCTION keep_chat_owner()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF NEW.owner_id = ANY (OLD.members) AND NOT NEW.owner_id = ANY (NEW.members) THEN
+    RAISE EXCEPTION 'owner % can''t be removed from chat %', NEW.owner_id, NEW.id;
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER keep_chat_owner_trigger
+  BEFORE UPDATE OF members ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION keep_chat_owner();
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- only the sha256 of an invitation token is stored, like refresh tokens
+ALTER TABLE workspace_invitations RENAME COLUMN token TO token_hash;
+
+UPDATE
+  workspace_invitations
+SET
+  token_hash = encode(sha256(convert_to(token_hash, 'UTF8')), 'hex');
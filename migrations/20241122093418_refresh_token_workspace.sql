@@ -0,0 +1,16 @@
+-- Add migration script here
+-- the workspace a refresh token family signs in to, access tokens renewed by it stay in it
+ALTER TABLE refresh_tokens
+  ADD COLUMN ws_id BIGINT REFERENCES workspaces(id) ON DELETE CASCADE;
+
+UPDATE
+  refresh_tokens t
+SET
+  ws_id = u.ws_id
+FROM
+  users u
+WHERE
+  u.id = t.user_id;
+
+ALTER TABLE refresh_tokens
+  ALTER COLUMN ws_id SET NOT NULL;
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- id of the access token issued along with the refresh token, signing out with the access token
+-- revokes the family of its session
+ALTER TABLE refresh_tokens
+  ADD COLUMN access_jti VARCHAR(32);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_access_jti_index ON refresh_tokens(access_jti);
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- messages pinned to the top of a chat
+CREATE TABLE IF NOT EXISTS chat_pins (
+  chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  pinned_by BIGINT NOT NULL REFERENCES users(id),
+  pinned_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chat_id, message_id)
+);
@@ -0,0 +1,20 @@
+-- Add migration script here
+-- if a member mutes or unmutes a chat, notify servers to reload the muted members of the chat
+CREATE OR REPLACE FUNCTION chat_member_muted()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF NEW.muted IS DISTINCT FROM OLD.muted THEN
+    RAISE NOTICE 'chat_member_muted: % %', NEW.chat_id, NEW.user_id;
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', 'MUTE', 'chat_id', NEW.chat_id)::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_member_muted_trigger
+  AFTER UPDATE OF muted ON chat_members
+  FOR EACH ROW
+  EXECUTE FUNCTION chat_member_muted();
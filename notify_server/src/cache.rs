@@ -0,0 +1,178 @@
+use crate::AppState;
+use anyhow::Result;
+use chat_core::{Chat, Message};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::time::interval;
+use tracing::warn;
+
+/// chats cached for the members of their notifications, the cache is cleared once it grows past this
+const CHAT_CACHE_CAPACITY: usize = 10_000;
+/// deleted chats are kept for this long to build their notifications
+const DELETED_CHAT_TTL: &str = "1 hour";
+const DELETED_CHAT_PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+pub(crate) fn setup_cache(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = interval(DELETED_CHAT_PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let ret =
+                sqlx::query("DELETE FROM deleted_chats WHERE deleted_at < NOW() - $1::interval")
+                    .bind(DELETED_CHAT_TTL)
+                    .execute(&state.pool)
+                    .await;
+            if let Err(e) = ret {
+                warn!("failed to prune deleted chats: {}", e);
+            }
+        }
+    });
+}
+
+/// Cached chat, load_chats should be called first for chats not seen yet
+pub(crate) fn get_chat(state: &AppState, chat_id: u64) -> Option<Arc<Chat>> {
+    state.chats.get(&chat_id).map(|v| v.clone())
+}
+
+/// Load the chats missing from the cache, and the ones to refresh even if cached
+pub(crate) async fn load_chats(
+    state: &AppState,
+    chat_ids: &HashSet<u64>,
+    refresh: &HashSet<u64>,
+) -> Result<()> {
+    let ids = chat_ids
+        .iter()
+        .chain(refresh)
+        .filter(|id| refresh.contains(id) || !state.chats.contains_key(id))
+        .map(|id| *id as i64)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let chats: Vec<Chat> = sqlx::query_as(
+        "
+        SELECT id, ws_id, name, type, members, owner_id, created_at
+        FROM chats
+        WHERE id = ANY($1)
+        ",
+    )
+    .bind(&ids)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let muted: Vec<(i64, i64)> = sqlx::query_as(
+        "
+        SELECT chat_id, user_id
+        FROM chat_members
+        WHERE chat_id = ANY($1) AND muted
+        ",
+    )
+    .bind(&ids)
+    .fetch_all(&state.pool)
+    .await?;
+
+    if state.chats.len() + chats.len() > CHAT_CACHE_CAPACITY {
+        clear_chats(state);
+    }
+    // refreshed chats not found are gone
+    for id in refresh {
+        state.chats.remove(id);
+        state.muted.remove(id);
+    }
+    for chat in chats {
+        state.muted.insert(chat.id as _, HashSet::new());
+        state.chats.insert(chat.id as _, Arc::new(chat));
+    }
+    for (chat_id, user_id) in muted {
+        if let Some(mut users) = state.muted.get_mut(&(chat_id as u64)) {
+            users.insert(user_id as _);
+        }
+    }
+    Ok(())
+}
+
+/// Members who muted the cached chat, they aren't notified of its new messages
+pub(crate) fn get_muted(state: &AppState, chat_id: u64) -> HashSet<u64> {
+    state
+        .muted
+        .get(&chat_id)
+        .map(|v| v.clone())
+        .unwrap_or_default()
+}
+
+/// Drop all cached chats, they're loaded again when needed
+pub(crate) fn clear_chats(state: &AppState) {
+    state.chats.clear();
+    state.muted.clear();
+}
+
+/// Remove a deleted chat from the cache, and load it from the deleted chats
+pub(crate) async fn load_deleted_chats(
+    state: &AppState,
+    chat_ids: &[i64],
+) -> Result<HashMap<i64, Chat>> {
+    if chat_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    for id in chat_ids {
+        state.chats.remove(&(*id as u64));
+    }
+
+    let chats: Vec<Chat> = sqlx::query_as(
+        "
+        SELECT id, ws_id, name, type, members, owner_id, created_at
+        FROM deleted_chats
+        WHERE id = ANY($1)
+        ",
+    )
+    .bind(chat_ids)
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(chats.into_iter().map(|v| (v.id, v)).collect())
+}
+
+/// Load messages by id, with one query for all of them
+pub(crate) async fn load_messages(
+    state: &AppState,
+    message_ids: &[i64],
+) -> Result<HashMap<i64, Message>> {
+    if message_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let messages: Vec<Message> = sqlx::query_as(
+        "
+        SELECT id, chat_id, sender_id, content, files, reply_to, thread_root_id,
+            reply_count, created_at, edited_at, deleted_at
+        FROM messages
+        WHERE id = ANY($1)
+        ",
+    )
+    .bind(message_ids)
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(messages.into_iter().map(|v| (v.id, v)).collect())
+}
+
+/// Other users of the workspaces of the user, they're notified of the presence of each other
+pub(crate) async fn load_workspace_peers(state: &AppState, user_id: i64) -> Result<Vec<i64>> {
+    let users: Vec<(i64,)> = sqlx::query_as(
+        "
+        SELECT DISTINCT m.user_id
+        FROM workspace_members m
+        JOIN workspace_members u ON u.ws_id = m.ws_id
+        WHERE u.user_id = $1 AND m.user_id <> $1
+            AND m.deactivated_at IS NULL AND u.deactivated_at IS NULL
+        ",
+    )
+    .bind(user_id)
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(users.into_iter().map(|(id,)| id).collect())
+}
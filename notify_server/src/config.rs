@@ -1,3 +1,4 @@
+use crate::fanout::FanoutKind;
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 use std::{env, fs::File};
@@ -12,6 +13,16 @@ pub struct AppConfig {
 pub struct ServerConfig {
     pub port: u16,
     pub db_url: String,
+    /// identifies this instance among the instances behind a load balancer
+    #[serde(default = "default_instance_id")]
+    pub instance_id: String,
+    /// backend for the ephemeral events published by notify server itself,
+    /// local is only used while no other instance is alive
+    #[serde(default)]
+    pub ephemeral_fanout: FanoutKind,
+    /// /metrics is served on its own port of the loopback interface, not exposed with the app
+    #[serde(default = "default_metrics_port")]
+    pub metrics_port: u16,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -36,3 +47,11 @@ impl AppConfig {
         Ok(ret?)
     }
 }
+
+fn default_metrics_port() -> u16 {
+    6689
+}
+
+fn default_instance_id() -> String {
+    env::var("HOSTNAME").unwrap_or_else(|_| "notify-server".to_string())
+}
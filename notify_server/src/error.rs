@@ -15,6 +15,12 @@ pub enum AppError {
 
     #[error("io error: {0}")]
     IoError(#[from] io::Error),
+
+    #[error("user {0} is deactivated in workspace {1}")]
+    Deactivated(i64, i64),
+
+    #[error("token of user {0} has been revoked")]
+    TokenRevoked(i64),
 }
 
 impl ErrorOutput {
@@ -28,7 +34,9 @@ impl ErrorOutput {
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
         let status = match &self {
-            Self::JwtError(_) => StatusCode::FORBIDDEN,
+            Self::JwtError(_) | Self::Deactivated(..) | Self::TokenRevoked(_) => {
+                StatusCode::FORBIDDEN
+            }
             Self::IoError(_) => StatusCode::SERVICE_UNAVAILABLE,
         };
 
@@ -0,0 +1,146 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgListener, PgPool};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
+use tracing::warn;
+
+const LOCAL_CHANNEL_CAPACITY: usize = 1024;
+
+/// A notification fanned out to notify server instances
+#[derive(Debug, Clone, PartialEq)]
+pub struct FanoutMessage {
+    pub channel: String,
+    pub payload: String,
+}
+
+/// Backend to fan out notifications, every subscribed instance gets each notification once
+#[async_trait]
+pub trait Fanout: Send + Sync + 'static {
+    /// Publish the payload on the channel
+    async fn publish(&self, channel: &str, payload: &str) -> Result<()>;
+
+    /// Notifications published on the channels, in the order they're published.
+    /// The stream ends once notifications could be missed, e.g. the connection is lost,
+    /// the subscriber should subscribe again.
+    async fn subscribe(
+        &self,
+        channels: &[&str],
+    ) -> Result<BoxStream<'static, Result<FanoutMessage>>>;
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FanoutKind {
+    /// Postgres LISTEN/NOTIFY, reaches all instances
+    #[default]
+    Postgres,
+    /// In process only, for a single instance
+    Local,
+}
+
+/// Postgres LISTEN/NOTIFY, the triggers of chat tables notify through it
+pub struct PgFanout {
+    db_url: String,
+    pool: PgPool,
+}
+
+/// Fan out within this instance, ephemeral events skip the round trip to Postgres
+pub struct LocalFanout {
+    tx: broadcast::Sender<FanoutMessage>,
+}
+
+impl PgFanout {
+    pub fn new(db_url: &str, pool: PgPool) -> Self {
+        Self {
+            db_url: db_url.to_string(),
+            pool,
+        }
+    }
+}
+
+#[async_trait]
+impl Fanout for PgFanout {
+    async fn publish(&self, channel: &str, payload: &str) -> Result<()> {
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(channel)
+            .bind(payload)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn subscribe(
+        &self,
+        channels: &[&str],
+    ) -> Result<BoxStream<'static, Result<FanoutMessage>>> {
+        let mut listener = PgListener::connect(&self.db_url).await?;
+        listener.listen_all(channels.iter().copied()).await?;
+
+        // unlike recv(), try_recv() tells when the connection is lost
+        let stream = stream::unfold(Some(listener), |listener| async move {
+            let mut listener = listener?;
+            match listener.try_recv().await {
+                Ok(Some(notif)) => {
+                    let msg = FanoutMessage {
+                        channel: notif.channel().to_string(),
+                        payload: notif.payload().to_string(),
+                    };
+                    Some((Ok(msg), Some(listener)))
+                }
+                Ok(None) => None,
+                Err(e) => Some((Err(e.into()), None)),
+            }
+        });
+        Ok(stream.boxed())
+    }
+}
+
+impl LocalFanout {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(LOCAL_CHANNEL_CAPACITY);
+        Self { tx }
+    }
+}
+
+impl Default for LocalFanout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Fanout for LocalFanout {
+    async fn publish(&self, channel: &str, payload: &str) -> Result<()> {
+        // nobody subscribed yet is fine, the notification is just dropped
+        let _ = self.tx.send(FanoutMessage {
+            channel: channel.to_string(),
+            payload: payload.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn subscribe(
+        &self,
+        channels: &[&str],
+    ) -> Result<BoxStream<'static, Result<FanoutMessage>>> {
+        let channels: Vec<String> = channels.iter().map(|v| v.to_string()).collect();
+        let stream = BroadcastStream::new(self.tx.subscribe()).filter_map(move |v| {
+            let ret = match v {
+                Ok(msg) if channels.contains(&msg.channel) => Some(Ok(msg)),
+                Ok(_) => None,
+                Err(e) => {
+                    warn!("local fanout: {}", e);
+                    None
+                }
+            };
+            futures::future::ready(ret)
+        });
+        Ok(stream.boxed())
+    }
+}
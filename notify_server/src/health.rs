@@ -0,0 +1,72 @@
+use crate::AppState;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+/// State of the listener of notifications
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListenerStatus {
+    pub connected: bool,
+    /// when the listener got connected or disconnected
+    pub since: DateTime<Utc>,
+    pub reconnects: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthOutput {
+    pub instance_id: String,
+    pub listener: ListenerStatus,
+}
+
+#[derive(Debug)]
+pub(crate) struct ListenerHealth(RwLock<ListenerStatus>);
+
+impl ListenerHealth {
+    pub(crate) fn new() -> Self {
+        Self(RwLock::new(ListenerStatus {
+            connected: false,
+            since: Utc::now(),
+            reconnects: 0,
+            last_error: None,
+        }))
+    }
+
+    pub(crate) fn status(&self) -> ListenerStatus {
+        self.0.read().expect("listener status poisoned").clone()
+    }
+
+    pub(crate) fn connected(&self, reconnected: bool) {
+        let mut status = self.0.write().expect("listener status poisoned");
+        status.connected = true;
+        status.since = Utc::now();
+        if reconnected {
+            status.reconnects += 1;
+        }
+    }
+
+    pub(crate) fn disconnected(&self, error: impl Into<String>) {
+        let mut status = self.0.write().expect("listener status poisoned");
+        status.connected = false;
+        status.since = Utc::now();
+        status.last_error = Some(error.into());
+    }
+}
+
+/// 503 while notifications can't be received, so that the load balancer could skip this instance
+pub(crate) async fn health_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let listener = state.listener.status();
+    let status = if listener.connected {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let output = HealthOutput {
+        instance_id: state.config.server.instance_id.clone(),
+        listener,
+    };
+    (status, Json(output))
+}
@@ -1,7 +1,15 @@
+mod cache;
 mod config;
 mod error;
+mod fanout;
+mod health;
+mod metrics;
 mod notif;
+mod presence;
+mod replay;
+mod session;
 mod sse;
+mod ws;
 
 use anyhow::Result;
 use axum::{
@@ -11,20 +19,55 @@ use axum::{
     routing::get,
     Router,
 };
-use chat_core::{verify_token, DecodingKey, TokenVerify, User};
+use cache::setup_cache;
+use chat_core::{
+    verify_token, Chat, DeactivatedMembers, DecodingKey, TokenId, TokenRevocations, TokenVerify,
+    User,
+};
 use dashmap::DashMap;
+use health::{health_handler, ListenerHealth};
+use metrics::{metrics_handler, Metrics};
+use presence::{setup_presence, UserActivity};
+use replay::{initial_event_id, setup_replay, ReplayLog};
+use session::Session;
+use sqlx::{postgres::PgPoolOptions, PgPool};
 use sse::sse_handler;
-use std::{ops::Deref, sync::Arc};
-use tokio::sync::broadcast;
+use std::{
+    collections::HashSet,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::{sync::broadcast, time::Instant};
 use tower_http::cors::{self, CorsLayer};
+use ws::ws_handler;
 
 pub use config::AppConfig;
 pub use error::AppError;
-pub use notif::{setup_pg_listener, AppEvent};
+pub use fanout::{Fanout, FanoutKind, FanoutMessage, LocalFanout, PgFanout};
+pub use health::{HealthOutput, ListenerStatus};
+pub use metrics::MetricsOutput;
+pub use notif::{setup_listener, AppEvent, ChatTyping};
+pub use replay::EventEnvelope;
+pub use ws::{WsCommand, WsReply};
 
 const INDEX_HTML: &str = include_str!("../index.html");
 
-pub type UserMap = Arc<DashMap<u64, broadcast::Sender<Arc<AppEvent>>>>;
+pub type UserMap = Arc<DashMap<u64, broadcast::Sender<EventEnvelope>>>;
+/// last typing time of a user in a chat, keyed by (chat_id, user_id)
+pub type TypingMap = Arc<DashMap<(u64, u64), Instant>>;
+/// connected users with their presence
+pub type PresenceMap = Arc<DashMap<u64, UserActivity>>;
+/// chats of recent notifications, for their members
+pub type ChatCache = Arc<DashMap<u64, Arc<Chat>>>;
+/// members who muted the cached chats, keyed by chat id
+pub type MutedCache = Arc<DashMap<u64, HashSet<u64>>>;
+/// recent events of each user, to replay to reconnecting clients
+pub type ReplayMap = Arc<DashMap<u64, ReplayLog>>;
+/// streams of each access token by jti, to close them once the token is revoked
+pub type SessionMap = Arc<DashMap<String, Session>>;
 
 #[derive(Clone)]
 pub struct AppState(Arc<AppStateInner>);
@@ -32,12 +75,40 @@ pub struct AppState(Arc<AppStateInner>);
 pub struct AppStateInner {
     pub config: AppConfig,
     users: UserMap,
+    typing: TypingMap,
+    presence: PresenceMap,
+    replay: ReplayMap,
+    chats: ChatCache,
+    muted: MutedCache,
+    /// their tokens are rejected, and they're left out of the notifications of the workspace
+    deactivated: DeactivatedMembers,
+    /// access tokens revoked by signing out
+    revocations: TokenRevocations,
+    sessions: SessionMap,
+    /// id of the latest event
+    event_id: AtomicU64,
+    /// events up to this id may be missing from the replay logs
+    replay_floor: AtomicU64,
+    metrics: Metrics,
+    /// state of the listener of notifications, reported by /health
+    listener: ListenerHealth,
+    /// fan-out of the notifications from chat server, through Postgres
+    fanout: Arc<dyn Fanout>,
+    /// fan-out of ephemeral events published by this server, the Postgres one if not set
+    ephemeral_fanout: Option<Arc<dyn Fanout>>,
+    /// other instances are alive, the local fan-out wouldn't reach their users
+    peers_alive: AtomicBool,
     dk: DecodingKey,
+    pool: PgPool,
 }
 
-pub async fn get_router(config: AppConfig) -> Result<Router> {
+/// The app, and the router of /metrics to be served on the internal port
+pub async fn get_router(config: AppConfig) -> Result<(Router, Router)> {
     let state = AppState::new(config);
-    setup_pg_listener(state.clone()).await?;
+    setup_listener(state.clone()).await?;
+    setup_presence(state.clone()).await?;
+    setup_replay(state.clone());
+    setup_cache(state.clone());
 
     let cors = CorsLayer::new()
         // allow `GET` and `POST` when accessing the resource
@@ -53,12 +124,18 @@ pub async fn get_router(config: AppConfig) -> Result<Router> {
 
     let app = Router::new()
         .route("/events", get(sse_handler))
+        .route("/ws", get(ws_handler))
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
         .layer(cors)
         .route("/", get(index_handler))
+        .route("/health", get(health_handler))
+        .with_state(state.clone());
+
+    let metrics = Router::new()
+        .route("/metrics", get(metrics_handler))
         .with_state(state);
 
-    Ok(app)
+    Ok((app, metrics))
 }
 
 async fn index_handler() -> impl IntoResponse {
@@ -68,8 +145,15 @@ async fn index_handler() -> impl IntoResponse {
 impl TokenVerify for AppState {
     type Error = AppError;
 
-    fn verify(&self, token: &str) -> std::result::Result<User, Self::Error> {
-        Ok(self.dk.verify(token)?)
+    fn verify(&self, token: &str) -> std::result::Result<(User, TokenId), Self::Error> {
+        let (user, id) = self.dk.verify_with_id(token)?;
+        if self.deactivated.is_deactivated(user.ws_id, user.id) {
+            return Err(AppError::Deactivated(user.id, user.ws_id));
+        }
+        if self.revocations.is_revoked(user.id, &id) {
+            return Err(AppError::TokenRevoked(user.id));
+        }
+        Ok((user, id))
     }
 }
 
@@ -82,9 +166,56 @@ impl Deref for AppState {
 }
 
 impl AppState {
+    /// Backend to publish ephemeral events, Postgres is used while other instances are alive.
+    /// They're received from Postgres either way.
+    pub(crate) fn ephemeral_fanout(&self) -> &dyn Fanout {
+        match &self.ephemeral_fanout {
+            Some(local) if !self.peers_alive.load(Ordering::Relaxed) => local.as_ref(),
+            _ => self.fanout.as_ref(),
+        }
+    }
+
     pub fn new(config: AppConfig) -> Self {
         let dk = DecodingKey::load(&config.auth.pk).expect("failed to load public key");
         let users = Arc::new(DashMap::new());
-        Self(Arc::new(AppStateInner { config, users, dk }))
+        let typing = Arc::new(DashMap::new());
+        let presence = Arc::new(DashMap::new());
+        let replay = Arc::new(DashMap::new());
+        let chats = Arc::new(DashMap::new());
+        let muted = Arc::new(DashMap::new());
+        let sessions = Arc::new(DashMap::new());
+        let event_id = AtomicU64::new(initial_event_id());
+        let replay_floor = AtomicU64::new(0);
+        let metrics = Metrics::default();
+        let listener = ListenerHealth::new();
+        let pool = PgPoolOptions::new()
+            .connect_lazy(&config.server.db_url)
+            .expect("failed to parse db url");
+        let fanout = Arc::new(PgFanout::new(&config.server.db_url, pool.clone()));
+        let ephemeral_fanout: Option<Arc<dyn Fanout>> = match config.server.ephemeral_fanout {
+            FanoutKind::Postgres => None,
+            FanoutKind::Local => Some(Arc::new(LocalFanout::new())),
+        };
+        Self(Arc::new(AppStateInner {
+            config,
+            users,
+            typing,
+            presence,
+            replay,
+            chats,
+            muted,
+            deactivated: DeactivatedMembers::default(),
+            revocations: TokenRevocations::default(),
+            sessions,
+            event_id,
+            replay_floor,
+            metrics,
+            listener,
+            fanout,
+            ephemeral_fanout,
+            peers_alive: AtomicBool::new(false),
+            dk,
+            pool,
+        }))
     }
 }
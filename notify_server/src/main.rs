@@ -22,8 +22,13 @@ async fn main() -> Result<()> {
     let listener = TcpListener::bind(addr).await?;
 
     let config = AppConfig::load()?;
-    let app = get_router(config).await?;
+    let metrics_addr = format!("127.0.0.1:{}", config.server.metrics_port);
+    info!("Metrics listening on {}", metrics_addr);
+    let metrics_listener = TcpListener::bind(&metrics_addr).await?;
 
+    let (app, metrics) = get_router(config).await?;
+
+    tokio::spawn(async move { axum::serve(metrics_listener, metrics).await });
     axum::serve(listener, app).await?;
 
     Ok(())
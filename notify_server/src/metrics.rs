@@ -0,0 +1,43 @@
+use crate::AppState;
+use axum::{extract::State, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of this instance
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// open SSE and websocket connections
+    pub(crate) connections: AtomicU64,
+    /// notifications received from the fan-out backends
+    pub(crate) notifications: AtomicU64,
+    /// events sent to connections, an event to N connections counts N
+    pub(crate) events_sent: AtomicU64,
+    /// events dropped by connections falling behind
+    pub(crate) events_lagged: AtomicU64,
+    pub(crate) resyncs: AtomicU64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsOutput {
+    pub instance_id: String,
+    pub connections: u64,
+    pub users: u64,
+    pub notifications: u64,
+    pub events_sent: u64,
+    pub events_lagged: u64,
+    pub resyncs: u64,
+}
+
+pub(crate) async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let metrics = &state.metrics;
+    Json(MetricsOutput {
+        instance_id: state.config.server.instance_id.clone(),
+        connections: metrics.connections.load(Ordering::Relaxed),
+        users: state.users.len() as _,
+        notifications: metrics.notifications.load(Ordering::Relaxed),
+        events_sent: metrics.events_sent.load(Ordering::Relaxed),
+        events_lagged: metrics.events_lagged.load(Ordering::Relaxed),
+        resyncs: metrics.resyncs.load(Ordering::Relaxed),
+    })
+}
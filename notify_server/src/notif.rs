@@ -1,19 +1,113 @@
-use crate::AppState;
+use crate::{
+    cache::{
+        clear_chats, get_chat, get_muted, load_chats, load_deleted_chats, load_messages,
+        load_workspace_peers,
+    },
+    presence::{instance_started, user_active, INSTANCE_STARTED_CHANNEL},
+    replay::{log_event, resync_all, EventEnvelope},
+    session::{member_deactivated, token_revoked},
+    AppState, FanoutMessage,
+};
 use anyhow::Result;
-use chat_core::{Chat, Message};
-use futures::StreamExt;
+use chat_core::{
+    Chat, ChatRead, MemberChanged, Message, MessageReaction, TokenRevoked, User, UserPresence,
+    MEMBER_CHANGED_CHANNEL, TOKEN_REVOKED_CHANNEL,
+};
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
 use serde::{Deserialize, Serialize};
-use sqlx::postgres::PgListener;
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::time::{sleep, Instant};
 use tracing::{info, warn};
 
+/// notifications loaded together at most
+const BATCH_SIZE: usize = 64;
+/// first wait before subscribing again, doubled after each failure
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// typing indicator stops after this long without another typing notification
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "event")]
 pub enum AppEvent {
     NewChat(Chat),
     AddToChat(Chat),
     RemoveFromChat(Chat),
+    /// The chat is renamed, switched between public and private, or has another owner
+    ChatUpdated(Chat),
     NewMessage(Message),
+    NewThreadReply(Message),
+    MessageUpdated(Message),
+    MessageDeleted(Message),
+    ReactionAdded(MessageReaction),
+    ReactionRemoved(MessageReaction),
+    ReadReceipt(ChatRead),
+    Typing(ChatTyping),
+    TypingStopped(ChatTyping),
+    PresenceChanged(UserPresence),
+    /// Events since the last one received are lost, the client should reload its state
+    Resync,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ChatTyping {
+    #[serde(alias = "chatId")]
+    pub chat_id: u64,
+    #[serde(alias = "userId")]
+    pub user_id: u64,
+}
+
+impl AppEvent {
+    /// The user whose action caused the event, it counts as an activity of the user
+    fn actor(&self) -> Option<u64> {
+        match self {
+            AppEvent::NewMessage(msg) | AppEvent::NewThreadReply(msg) => Some(msg.sender_id as _),
+            AppEvent::ReactionAdded(reaction) | AppEvent::ReactionRemoved(reaction) => {
+                Some(reaction.user_id as _)
+            }
+            AppEvent::ReadReceipt(read) => Some(read.user_id as _),
+            AppEvent::Typing(typing) => Some(typing.user_id),
+            _ => None,
+        }
+    }
+
+    /// Workspace of the chat of the event, presence events aren't scoped to a workspace
+    pub(crate) fn ws_id(&self, state: &AppState) -> Option<u64> {
+        match self {
+            AppEvent::NewChat(chat)
+            | AppEvent::AddToChat(chat)
+            | AppEvent::RemoveFromChat(chat)
+            | AppEvent::ChatUpdated(chat) => Some(chat.ws_id as _),
+            _ => self
+                .chat_id()
+                .and_then(|chat_id| get_chat(state, chat_id))
+                .map(|chat| chat.ws_id as _),
+        }
+    }
+
+    /// The chat of an activity event, chat membership and presence events aren't scoped to a chat
+    pub(crate) fn chat_id(&self) -> Option<u64> {
+        match self {
+            AppEvent::NewMessage(msg)
+            | AppEvent::NewThreadReply(msg)
+            | AppEvent::MessageUpdated(msg)
+            | AppEvent::MessageDeleted(msg) => Some(msg.chat_id as _),
+            AppEvent::ReactionAdded(reaction) | AppEvent::ReactionRemoved(reaction) => {
+                Some(reaction.chat_id as _)
+            }
+            AppEvent::ReadReceipt(read) => Some(read.chat_id as _),
+            AppEvent::Typing(typing) | AppEvent::TypingStopped(typing) => Some(typing.chat_id),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,109 +116,507 @@ struct Notification {
     event: Arc<AppEvent>,
 }
 
+// pg_notify payloads are limited to 8000 bytes, so notifications only carry ids and small rows,
+// messages and chats are loaded from the database
+
 #[derive(Debug, Serialize, Deserialize)]
 struct ChatUpdated {
     op: String,
-    old: Option<Chat>,
-    new: Option<Chat>,
+    chat_id: i64,
+    #[serde(default)]
+    members_changed: bool,
+    /// members removed by an update
+    #[serde(default)]
+    removed: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatMessageChanged {
+    message_id: i64,
+    chat_id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MessageReactionChanged {
+    op: String,
+    reaction: MessageReaction,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct ChatMessageCreated {
-    message: Message,
-    members: Vec<i64>,
+struct ChatReadUpdated {
+    read: ChatRead,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UserPresenceChanged {
+    presence: UserPresence,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatTypingUpdated {
+    typing: ChatTyping,
+}
+
+/// A notification as received, before its rows are loaded
+#[derive(Debug)]
+enum Payload {
+    ChatUpdated(ChatUpdated),
+    MessageChanged(String, ChatMessageChanged),
+    ReactionChanged(MessageReactionChanged),
+    ReadUpdated(ChatReadUpdated),
+    Typing(ChatTypingUpdated),
+    PresenceChanged(UserPresenceChanged),
+    MemberChanged(MemberChanged),
+    TokenRevoked(TokenRevoked),
+    InstanceStarted(String),
 }
 
-pub async fn setup_pg_listener(state: AppState) -> Result<()> {
-    let mut listener = PgListener::connect(&state.config.server.db_url).await?;
+/// Rows referenced by a batch of notifications
+struct BatchRows {
+    messages: HashMap<i64, Message>,
+    deleted_chats: HashMap<i64, Chat>,
+}
 
-    listener.listen("chat_updated").await?;
-    listener.listen("chat_message_created").await?;
+/// channels of the notifications from chat server
+const CHANNELS: [&str; 11] = [
+    "chat_updated",
+    "chat_message_created",
+    "chat_message_updated",
+    "chat_message_deleted",
+    "message_reaction_changed",
+    "chat_read_updated",
+    "chat_typing",
+    "user_presence_changed",
+    MEMBER_CHANGED_CHANNEL,
+    TOKEN_REVOKED_CHANNEL,
+    INSTANCE_STARTED_CHANNEL,
+];
+/// channels of the ephemeral events published by notify server
+const EPHEMERAL_CHANNELS: [&str; 1] = ["chat_typing"];
 
-    let mut stream = listener.into_stream();
+pub async fn setup_listener(state: AppState) -> Result<()> {
+    let stream = subscribe(&state).await?;
+    state.deactivated.load(&state.pool).await?;
+    state.revocations.load(&state.pool).await?;
+    state.listener.connected(false);
 
     tokio::spawn(async move {
-        while let Some(Ok(notif)) = stream.next().await {
+        let mut stream = stream;
+        loop {
+            let e = listen(&state, stream).await;
+            warn!("notification listener stopped: {}", e);
+            state.listener.disconnected(e.to_string());
+
+            stream = resubscribe(&state).await;
+            state.listener.connected(true);
+            info!("notification listener reconnected");
+            // chats may have changed, and events are missed while disconnected
+            clear_chats(&state);
+            if let Err(e) = state.deactivated.load(&state.pool).await {
+                warn!("failed to load deactivated members: {}", e);
+            }
+            if let Err(e) = state.revocations.load(&state.pool).await {
+                warn!("failed to load revoked tokens: {}", e);
+            }
+            resync_all(&state);
+        }
+    });
+    Ok(())
+}
+
+/// Subscribe to notifications of all the backends, the stream fails once any of them ends
+async fn subscribe(state: &AppState) -> Result<BoxStream<'static, Result<FanoutMessage>>> {
+    let ended = || stream::once(async { Err(anyhow::anyhow!("notification stream ended")) });
+    let mut stream = state
+        .fanout
+        .subscribe(&CHANNELS)
+        .await?
+        .chain(ended())
+        .boxed();
+    if let Some(ephemeral) = &state.ephemeral_fanout {
+        let ephemeral = ephemeral.subscribe(&EPHEMERAL_CHANNELS).await?;
+        stream = stream::select(stream, ephemeral.chain(ended())).boxed();
+    }
+    Ok(stream)
+}
+
+/// Subscribe again until it succeeds, waiting longer after each failure
+async fn resubscribe(state: &AppState) -> BoxStream<'static, Result<FanoutMessage>> {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        sleep(backoff).await;
+        match subscribe(state).await {
+            Ok(stream) => return stream,
+            Err(e) => {
+                warn!("failed to subscribe to notifications: {}", e);
+                state.listener.disconnected(e.to_string());
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Send events of the notifications until the stream fails.
+/// A notification that can't be loaded is skipped, it doesn't stop the others.
+async fn listen(
+    state: &AppState,
+    stream: BoxStream<'static, Result<FanoutMessage>>,
+) -> anyhow::Error {
+    // notifications arriving together are loaded together
+    let mut stream = stream.ready_chunks(BATCH_SIZE);
+    while let Some(batch) = stream.next().await {
+        let mut payloads = Vec::with_capacity(batch.len());
+        let mut error = None;
+        for notif in batch {
+            let notif = match notif {
+                Ok(notif) => notif,
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            };
             info!("Received notification: {:?}", notif);
+            state.metrics.notifications.fetch_add(1, Ordering::Relaxed);
+            match Payload::parse(&notif.channel, &notif.payload) {
+                Ok(payload) => payloads.push(payload),
+                Err(e) => warn!("invalid notification {:?}: {}", notif, e),
+            }
+        }
 
-            let notif = Notification::load(notif.channel(), notif.payload())?;
+        send_notifications(state, payloads).await;
+        if let Some(e) = error {
+            return e;
+        }
+    }
+    anyhow::anyhow!("notification stream ended")
+}
 
-            let users = &state.users;
+async fn send_notifications(state: &AppState, payloads: Vec<Payload>) {
+    let rows = match BatchRows::load(state, &payloads).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            warn!("failed to load rows of notifications: {}", e);
+            return;
+        }
+    };
 
-            for user_id in notif.user_ids {
-                if let Some(tx) = users.get(&user_id) {
-                    if let Err(e) = tx.send(notif.event.clone()) {
-                        warn!("failed to send notif to user {}: {}", user_id, e);
-                    }
+    for payload in payloads {
+        // not an event, but sent in order with the others
+        match &payload {
+            Payload::MemberChanged(changed) => {
+                member_changed(state, changed);
+                continue;
+            }
+            Payload::TokenRevoked(revoked) => {
+                token_revoked(state, revoked);
+                continue;
+            }
+            Payload::InstanceStarted(instance_id) => {
+                instance_started(state, instance_id);
+                continue;
+            }
+            _ => {}
+        }
+        let notifs = match Notification::load(state, &rows, payload).await {
+            Ok(notifs) => notifs,
+            Err(e) => {
+                warn!("failed to load notification: {}", e);
+                continue;
+            }
+        };
+        for notif in notifs {
+            send_notification(state, notif).await;
+        }
+    }
+}
+
+async fn send_notification(state: &AppState, notif: Notification) {
+    if let Some(user_id) = notif.event.actor() {
+        user_active(state, user_id).await;
+    }
+
+    if let AppEvent::Typing(typing) = notif.event.as_ref() {
+        // only the start of typing is sent, later ones just keep it alive
+        if !track_typing(state, typing, &notif.user_ids) {
+            return;
+        }
+    }
+
+    send_event(state, &notif.user_ids, notif.event);
+}
+
+/// Block or unblock the tokens of the member, a deactivated member is disconnected
+fn member_changed(state: &AppState, changed: &MemberChanged) {
+    state.deactivated.apply(changed);
+    if !changed.active {
+        member_deactivated(state, changed.ws_id, changed.user_id);
+    }
+}
+
+/// Publish typing of the user through the ephemeral fan-out, return false if the user isn't in the chat
+pub(crate) async fn publish_typing(state: &AppState, chat_id: u64, user: &User) -> Result<bool> {
+    if !is_chat_member(state, chat_id, user).await? {
+        return Ok(false);
+    }
+
+    let payload = ChatTypingUpdated {
+        typing: ChatTyping {
+            chat_id,
+            user_id: user.id as _,
+        },
+    };
+    state
+        .ephemeral_fanout()
+        .publish("chat_typing", &serde_json::to_string(&payload)?)
+        .await?;
+    Ok(true)
+}
+
+/// Whether the user is a member of the chat, in the workspace of its token
+pub(crate) async fn is_chat_member(state: &AppState, chat_id: u64, user: &User) -> Result<bool> {
+    load_chats(state, &HashSet::from([chat_id]), &HashSet::new()).await?;
+    let is_member = get_chat(state, chat_id)
+        .map(|chat| chat.ws_id == user.ws_id && chat.members.contains(&user.id))
+        .unwrap_or(false);
+    Ok(is_member)
+}
+
+impl Payload {
+    fn parse(r#type: &str, payload: &str) -> Result<Self> {
+        let payload = match r#type {
+            "chat_updated" => Self::ChatUpdated(serde_json::from_str(payload)?),
+            "chat_message_created" | "chat_message_updated" | "chat_message_deleted" => {
+                Self::MessageChanged(r#type.to_string(), serde_json::from_str(payload)?)
+            }
+            "message_reaction_changed" => Self::ReactionChanged(serde_json::from_str(payload)?),
+            "chat_read_updated" => Self::ReadUpdated(serde_json::from_str(payload)?),
+            "chat_typing" => Self::Typing(serde_json::from_str(payload)?),
+            "user_presence_changed" => Self::PresenceChanged(serde_json::from_str(payload)?),
+            MEMBER_CHANGED_CHANNEL => Self::MemberChanged(serde_json::from_str(payload)?),
+            TOKEN_REVOKED_CHANNEL => Self::TokenRevoked(serde_json::from_str(payload)?),
+            INSTANCE_STARTED_CHANNEL => Self::InstanceStarted(payload.to_string()),
+            _ => return Err(anyhow::anyhow!("Invalid notification type")),
+        };
+        Ok(payload)
+    }
+
+    /// The chat whose members receive the notification
+    fn chat_id(&self) -> Option<u64> {
+        match self {
+            Self::ChatUpdated(payload) => Some(payload.chat_id as _),
+            Self::MessageChanged(_, payload) => Some(payload.chat_id as _),
+            Self::ReactionChanged(payload) => Some(payload.reaction.chat_id as _),
+            Self::ReadUpdated(payload) => Some(payload.read.chat_id as _),
+            Self::Typing(payload) => Some(payload.typing.chat_id),
+            Self::PresenceChanged(_)
+            | Self::MemberChanged(_)
+            | Self::TokenRevoked(_)
+            | Self::InstanceStarted(_) => None,
+        }
+    }
+}
+
+impl BatchRows {
+    /// Load the rows of a batch, with one query for each table.
+    /// Chats changed are loaded again, the others come from the cache if they're there.
+    async fn load(state: &AppState, payloads: &[Payload]) -> Result<Self> {
+        let mut chat_ids = HashSet::new();
+        let mut refresh = HashSet::new();
+        let mut deleted = vec![];
+        let mut message_ids = vec![];
+        for payload in payloads {
+            match payload {
+                Payload::ChatUpdated(payload) if payload.op == "DELETE" => {
+                    deleted.push(payload.chat_id)
+                }
+                Payload::ChatUpdated(payload) => {
+                    refresh.insert(payload.chat_id as u64);
                 }
+                Payload::MessageChanged(_, payload) => message_ids.push(payload.message_id),
+                _ => {}
             }
+            chat_ids.extend(payload.chat_id());
         }
-        Ok::<_, anyhow::Error>(())
-    });
-    Ok(())
+
+        load_chats(state, &chat_ids, &refresh).await?;
+        Ok(Self {
+            messages: load_messages(state, &message_ids).await?,
+            deleted_chats: load_deleted_chats(state, &deleted).await?,
+        })
+    }
 }
 
 impl Notification {
-    fn load(r#type: &str, payload: &str) -> Result<Self> {
-        match r#type {
-            "chat_updated" => {
-                let payload: ChatUpdated = serde_json::from_str(payload)?;
-                info!("ChatUpdated: {:?}", payload);
-                let user_ids =
-                    get_affected_chat_user_ids(payload.old.as_ref(), payload.new.as_ref());
-                let event = match payload.op.as_str() {
-                    "INSERT" => AppEvent::NewChat(payload.new.expect("new should exist")),
-                    "UPDATE" => AppEvent::AddToChat(payload.new.expect("new should exist")),
-                    "DELETE" => AppEvent::RemoveFromChat(payload.old.expect("old should exist")),
+    /// Notifications to send for the payload, a chat update notifies removed members differently
+    async fn load(state: &AppState, rows: &BatchRows, payload: Payload) -> Result<Vec<Self>> {
+        let members = match payload.chat_id() {
+            Some(chat_id) => get_chat(state, chat_id).map(|chat| chat_user_ids(state, &chat)),
+            None => None,
+        };
+        let chat_members = || {
+            members
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("chat of {:?} not found", payload))
+        };
+
+        let (user_ids, event) = match &payload {
+            Payload::ChatUpdated(updated) => {
+                info!("ChatUpdated: {:?}", updated);
+                match updated.op.as_str() {
+                    "INSERT" => {
+                        let chat = get_chat(state, updated.chat_id as _)
+                            .ok_or_else(|| anyhow::anyhow!("chat {} not found", updated.chat_id))?;
+                        (chat_members()?, AppEvent::NewChat(chat.as_ref().clone()))
+                    }
+                    "UPDATE" => {
+                        let chat = get_chat(state, updated.chat_id as _)
+                            .ok_or_else(|| anyhow::anyhow!("chat {} not found", updated.chat_id))?;
+                        let chat = chat.as_ref().clone();
+                        if !updated.members_changed {
+                            (chat_members()?, AppEvent::ChatUpdated(chat))
+                        } else {
+                            // members get the updated chat, removed users are told they're out
+                            let removed: HashSet<u64> =
+                                updated.removed.iter().map(|v| *v as u64).collect();
+                            let mut notifs = vec![Self {
+                                user_ids: chat_members()?,
+                                event: Arc::new(AppEvent::AddToChat(chat.clone())),
+                            }];
+                            if !removed.is_empty() {
+                                notifs.push(Self {
+                                    user_ids: removed,
+                                    event: Arc::new(AppEvent::RemoveFromChat(chat)),
+                                });
+                            }
+                            return Ok(notifs);
+                        }
+                    }
+                    // muted members are reloaded with the chat, nothing to tell
+                    "MUTE" => return Ok(vec![]),
+                    "DELETE" => {
+                        let chat = rows
+                            .deleted_chats
+                            .get(&updated.chat_id)
+                            .ok_or_else(|| anyhow::anyhow!("chat {} not found", updated.chat_id))?;
+                        (
+                            chat_user_ids(state, chat),
+                            AppEvent::RemoveFromChat(chat.clone()),
+                        )
+                    }
                     _ => return Err(anyhow::anyhow!("Invalid operation")),
+                }
+            }
+            Payload::MessageChanged(r#type, changed) => {
+                let message = rows
+                    .messages
+                    .get(&changed.message_id)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("message {} not found", changed.message_id))?;
+                let mut user_ids = chat_members()?;
+                if r#type == "chat_message_created" {
+                    // members who muted the chat aren't notified, except of their own messages
+                    for user_id in get_muted(state, changed.chat_id as _) {
+                        if user_id != message.sender_id as u64 {
+                            user_ids.remove(&user_id);
+                        }
+                    }
+                }
+                let event = match r#type.as_str() {
+                    "chat_message_created" if message.thread_root_id.is_some() => {
+                        AppEvent::NewThreadReply(message)
+                    }
+                    "chat_message_created" => AppEvent::NewMessage(message),
+                    "chat_message_updated" => AppEvent::MessageUpdated(message),
+                    _ => AppEvent::MessageDeleted(message),
                 };
-                Ok(Self {
-                    user_ids,
-                    event: Arc::new(event),
-                })
+                (user_ids, event)
+            }
+            Payload::ReactionChanged(changed) => {
+                let event = match changed.op.as_str() {
+                    "INSERT" => AppEvent::ReactionAdded(changed.reaction.clone()),
+                    "DELETE" => AppEvent::ReactionRemoved(changed.reaction.clone()),
+                    _ => return Err(anyhow::anyhow!("Invalid operation")),
+                };
+                (chat_members()?, event)
+            }
+            Payload::ReadUpdated(updated) => {
+                (chat_members()?, AppEvent::ReadReceipt(updated.read.clone()))
             }
-            "chat_message_created" => {
-                let payload: ChatMessageCreated = serde_json::from_str(payload)?;
-                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
-                Ok(Self {
+            Payload::Typing(updated) => {
+                let mut user_ids = chat_members()?;
+                user_ids.remove(&updated.typing.user_id);
+                (user_ids, AppEvent::Typing(updated.typing.clone()))
+            }
+            Payload::PresenceChanged(changed) => {
+                let user_ids = load_workspace_peers(state, changed.presence.user_id)
+                    .await?
+                    .into_iter()
+                    .map(|v| v as u64)
+                    .collect();
+                (
                     user_ids,
-                    event: Arc::new(AppEvent::NewMessage(payload.message)),
-                })
+                    AppEvent::PresenceChanged(changed.presence.clone()),
+                )
             }
-            _ => Err(anyhow::anyhow!("Invalid notification type")),
-        }
+            // handled by member_changed and token_revoked
+            Payload::MemberChanged(_) | Payload::TokenRevoked(_) | Payload::InstanceStarted(_) => {
+                return Ok(vec![])
+            }
+        };
+        Ok(vec![Self {
+            user_ids,
+            event: Arc::new(event),
+        }])
     }
 }
 
-fn get_affected_chat_user_ids(old: Option<&Chat>, new: Option<&Chat>) -> HashSet<u64> {
-    match (old, new) {
-        (Some(old), Some(new)) => {
-            let old_user_ids = old
-                .members
-                .iter()
-                .map(|v| *v as u64)
-                .collect::<HashSet<_>>();
-            let new_user_ids = new
-                .members
-                .iter()
-                .map(|v| *v as u64)
-                .collect::<HashSet<_>>();
-            if old_user_ids == new_user_ids {
-                HashSet::new()
-            } else {
-                old_user_ids.union(&new_user_ids).copied().collect()
+fn send_event(state: &AppState, user_ids: &HashSet<u64>, event: Arc<AppEvent>) {
+    let event = EventEnvelope::new(state, event);
+    for user_id in user_ids {
+        log_event(state, *user_id, &event);
+        if let Some(tx) = state.users.get(user_id) {
+            match tx.send(event.clone()) {
+                Ok(n) => {
+                    state
+                        .metrics
+                        .events_sent
+                        .fetch_add(n as _, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    warn!("failed to send notif to user {}: {}", user_id, e);
+                }
             }
         }
-        (Some(old), None) => old
-            .members
-            .iter()
-            .map(|v| *v as u64)
-            .collect::<HashSet<_>>(),
-        (None, Some(new)) => new
-            .members
-            .iter()
-            .map(|v| *v as u64)
-            .collect::<HashSet<_>>(),
-        _ => HashSet::new(),
     }
 }
+
+/// Record the latest typing of the user in the chat, return true if the user just started typing.
+/// A TypingStopped event is sent once the user is silent for TYPING_TIMEOUT.
+fn track_typing(state: &AppState, typing: &ChatTyping, user_ids: &HashSet<u64>) -> bool {
+    let key = (typing.chat_id, typing.user_id);
+    let started = state.typing.insert(key, Instant::now()).is_none();
+
+    let state = state.clone();
+    let typing = typing.clone();
+    let user_ids = user_ids.clone();
+    tokio::spawn(async move {
+        sleep(TYPING_TIMEOUT).await;
+        let expired = state
+            .typing
+            .remove_if(&key, |_, last| last.elapsed() >= TYPING_TIMEOUT)
+            .is_some();
+        if expired {
+            send_event(&state, &user_ids, Arc::new(AppEvent::TypingStopped(typing)));
+        }
+    });
+    started
+}
+
+/// Members of the chat, the ones deactivated in its workspace are left out
+fn chat_user_ids(state: &AppState, chat: &Chat) -> HashSet<u64> {
+    chat.members
+        .iter()
+        .filter(|v| !state.deactivated.is_deactivated(chat.ws_id, **v))
+        .map(|v| *v as u64)
+        .collect()
+}
@@ -0,0 +1,226 @@
+use crate::AppState;
+use chat_core::PresenceStatus;
+use sqlx::PgPool;
+use std::{sync::atomic::Ordering, time::Duration};
+use tokio::time::{interval, Instant};
+use tracing::{info, warn};
+
+/// a connected user becomes away after this long without any activity
+const AWAY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const AWAY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// users of an instance without heartbeat for this long are offline
+const INSTANCE_TIMEOUT: &str = "2 minutes";
+/// an instance tells the others it's started, before they see its heartbeat
+pub(crate) const INSTANCE_STARTED_CHANNEL: &str = "notify_instance_started";
+
+#[derive(Debug, Clone)]
+pub struct UserActivity {
+    ws_id: u64,
+    status: PresenceStatus,
+    last_active: Instant,
+    /// open SSE streams and websockets of the user
+    connections: usize,
+}
+
+/// Dropped with the SSE stream of a user, to find out if the user is gone
+pub(crate) struct PresenceGuard {
+    state: AppState,
+    user_id: u64,
+}
+
+pub(crate) async fn setup_presence(state: AppState) -> anyhow::Result<()> {
+    // no connection survives a restart, whoever was online on this instance is gone
+    sqlx::query(
+        "UPDATE user_presence SET status = 'offline' WHERE instance_id = $1 AND status <> 'offline'",
+    )
+    .bind(&state.config.server.instance_id)
+    .execute(&state.pool)
+    .await?;
+    state
+        .fanout
+        .publish(INSTANCE_STARTED_CHANNEL, &state.config.server.instance_id)
+        .await?;
+
+    tokio::spawn(async move {
+        let mut interval = interval(AWAY_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            match heartbeat(&state.pool, &state.config.server.instance_id).await {
+                Ok(peers) => set_peers_alive(&state, peers > 0),
+                Err(e) => warn!("failed to send heartbeat: {}", e),
+            }
+
+            let mut away = Vec::new();
+            for mut activity in state.presence.iter_mut() {
+                if activity.status == PresenceStatus::Online
+                    && activity.last_active.elapsed() >= AWAY_TIMEOUT
+                {
+                    activity.status = PresenceStatus::Away;
+                    away.push((*activity.key(), activity.ws_id));
+                }
+            }
+            for (user_id, ws_id) in away {
+                save_presence(&state, user_id, ws_id, PresenceStatus::Away).await;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Mark the user online when a SSE stream of the user is opened
+pub(crate) async fn user_connected(state: &AppState, user_id: u64, ws_id: u64) -> PresenceGuard {
+    state.metrics.connections.fetch_add(1, Ordering::Relaxed);
+    let changed = {
+        let mut activity = state
+            .presence
+            .entry(user_id)
+            .or_insert_with(|| UserActivity {
+                ws_id,
+                status: PresenceStatus::Offline,
+                last_active: Instant::now(),
+                connections: 0,
+            });
+        activity.last_active = Instant::now();
+        activity.connections += 1;
+        std::mem::replace(&mut activity.status, PresenceStatus::Online) != PresenceStatus::Online
+    };
+    if changed {
+        save_presence(state, user_id, ws_id, PresenceStatus::Online).await;
+    }
+    PresenceGuard {
+        state: state.clone(),
+        user_id,
+    }
+}
+
+/// Record an activity of the user, it brings a connected user back from away
+pub(crate) async fn user_active(state: &AppState, user_id: u64) {
+    let ws_id = match state.presence.get_mut(&user_id) {
+        Some(mut activity) => {
+            activity.last_active = Instant::now();
+            if activity.status == PresenceStatus::Away {
+                activity.status = PresenceStatus::Online;
+                Some(activity.ws_id)
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+    if let Some(ws_id) = ws_id {
+        save_presence(state, user_id, ws_id, PresenceStatus::Online).await;
+    }
+}
+
+/// Release the sender of the user once its last SSE stream is gone, and mark the user offline
+/// once it has no connection left
+async fn user_disconnected(state: &AppState, user_id: u64, gone: Option<u64>) {
+    state
+        .users
+        .remove_if(&user_id, |_, tx| tx.receiver_count() == 0);
+    let Some(ws_id) = gone else {
+        return;
+    };
+    // connected again in the meantime, it's online already
+    if state.presence.contains_key(&user_id) {
+        return;
+    }
+    info!("User {} disconnected", user_id);
+    save_presence(state, user_id, ws_id, PresenceStatus::Offline).await;
+}
+
+/// Another instance is started, ephemeral events must reach it from now on
+pub(crate) fn instance_started(state: &AppState, instance_id: &str) {
+    if instance_id != state.config.server.instance_id {
+        set_peers_alive(state, true);
+    }
+}
+
+fn set_peers_alive(state: &AppState, alive: bool) {
+    if state.peers_alive.swap(alive, Ordering::Relaxed) != alive {
+        info!("other notify server instances alive: {}", alive);
+    }
+}
+
+/// Keep this instance alive, mark users of instances gone for a while offline,
+/// and return the number of the other instances alive
+async fn heartbeat(pool: &PgPool, instance_id: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query(
+        "
+        INSERT INTO notify_instances (instance_id)
+        VALUES ($1)
+        ON CONFLICT (instance_id) DO UPDATE SET seen_at = NOW()
+        ",
+    )
+    .bind(instance_id)
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "
+        UPDATE user_presence
+        SET status = 'offline', updated_at = NOW()
+        WHERE status <> 'offline' AND instance_id NOT IN (
+            SELECT instance_id FROM notify_instances WHERE seen_at > NOW() - $1::interval
+        )
+        ",
+    )
+    .bind(INSTANCE_TIMEOUT)
+    .execute(pool)
+    .await?;
+
+    let (peers,): (i64,) = sqlx::query_as(
+        "
+        SELECT COUNT(*)
+        FROM notify_instances
+        WHERE instance_id <> $1 AND seen_at > NOW() - $2::interval
+        ",
+    )
+    .bind(instance_id)
+    .bind(INSTANCE_TIMEOUT)
+    .fetch_one(pool)
+    .await?;
+    Ok(peers)
+}
+
+/// Save the presence of the user on this instance
+async fn save_presence(state: &AppState, user_id: u64, ws_id: u64, status: PresenceStatus) {
+    let ret = sqlx::query(
+        "
+        INSERT INTO user_presence (user_id, instance_id, ws_id, status)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id, instance_id) DO UPDATE
+        SET ws_id = EXCLUDED.ws_id, status = EXCLUDED.status, updated_at = NOW()
+        ",
+    )
+    .bind(user_id as i64)
+    .bind(&state.config.server.instance_id)
+    .bind(ws_id as i64)
+    .bind(status)
+    .execute(&state.pool)
+    .await;
+    if let Err(e) = ret {
+        warn!("failed to save presence of user {}: {}", user_id, e);
+    }
+}
+
+impl Drop for PresenceGuard {
+    fn drop(&mut self) {
+        self.state
+            .metrics
+            .connections
+            .fetch_sub(1, Ordering::Relaxed);
+        let gone = self
+            .state
+            .presence
+            .remove_if_mut(&self.user_id, |_, activity| {
+                activity.connections -= 1;
+                activity.connections == 0
+            })
+            .map(|(_, activity)| activity.ws_id);
+        let state = self.state.clone();
+        let user_id = self.user_id;
+        // the receiver of the stream may not be dropped yet, check it afterwards
+        tokio::spawn(async move { user_disconnected(&state, user_id, gone).await });
+    }
+}
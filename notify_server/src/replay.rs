@@ -0,0 +1,226 @@
+use crate::{AppEvent, AppState};
+use std::{
+    collections::VecDeque,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::{interval, Instant};
+use tracing::warn;
+
+/// events kept for each user to replay to a reconnecting client
+const REPLAY_CAPACITY: usize = 256;
+/// replay logs of users gone for this long are dropped
+const REPLAY_TTL: Duration = Duration::from_secs(10 * 60);
+const REPLAY_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An event sent to users, ephemeral events like typing have no id and are never replayed
+#[derive(Debug, Clone)]
+pub struct EventEnvelope {
+    pub id: Option<u64>,
+    pub event: Arc<AppEvent>,
+    /// workspace of the chat of the event, events of other workspaces aren't sent to a stream
+    pub ws_id: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct ReplayLog {
+    events: VecDeque<EventEnvelope>,
+    /// id of the latest event evicted from the log
+    evicted: u64,
+    updated_at: Instant,
+}
+
+/// Position of a client in its event stream, used to skip duplicates and fill the gaps
+pub(crate) struct EventCursor {
+    state: AppState,
+    user_id: u64,
+    last_id: u64,
+}
+
+/// Ids start from the current time so that they keep increasing across restarts
+pub(crate) fn initial_event_id() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before unix epoch")
+        .as_micros() as u64
+}
+
+/// Event id sent to clients, ids of different instances are unrelated so the instance is part of it
+pub(crate) fn format_event_id(state: &AppState, id: u64) -> String {
+    format!("{}-{}", state.config.server.instance_id, id)
+}
+
+/// Parse an event id sent by a client, None if it's not from this instance
+pub(crate) fn parse_event_id(state: &AppState, v: &str) -> Option<u64> {
+    let (instance_id, id) = v.rsplit_once('-')?;
+    if instance_id != state.config.server.instance_id {
+        return None;
+    }
+    id.parse().ok()
+}
+
+pub(crate) fn setup_replay(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = interval(REPLAY_PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            state.replay.retain(|user_id, log| {
+                let keep =
+                    state.users.contains_key(user_id) || log.updated_at.elapsed() < REPLAY_TTL;
+                if !keep {
+                    // the events of the user can't be replayed anymore
+                    if let Some(id) = log.events.back().and_then(|e| e.id) {
+                        state.replay_floor.fetch_max(id, Ordering::SeqCst);
+                    }
+                }
+                keep
+            });
+        }
+    });
+}
+
+impl EventEnvelope {
+    pub(crate) fn new(state: &AppState, event: Arc<AppEvent>) -> Self {
+        let id = match event.as_ref() {
+            AppEvent::Typing(_) | AppEvent::TypingStopped(_) | AppEvent::Resync => None,
+            _ => Some(state.event_id.fetch_add(1, Ordering::SeqCst) + 1),
+        };
+        let ws_id = event.ws_id(state);
+        Self { id, event, ws_id }
+    }
+
+    fn resync() -> Self {
+        Self {
+            id: None,
+            event: Arc::new(AppEvent::Resync),
+            ws_id: None,
+        }
+    }
+
+    /// Whether the event is sent to a stream of a token of the workspace
+    pub(crate) fn visible_in(&self, ws_id: u64) -> bool {
+        self.ws_id.is_none_or(|v| v == ws_id)
+    }
+}
+
+impl ReplayLog {
+    fn new() -> Self {
+        Self {
+            events: VecDeque::with_capacity(REPLAY_CAPACITY),
+            evicted: 0,
+            updated_at: Instant::now(),
+        }
+    }
+
+    fn push(&mut self, event: EventEnvelope) {
+        if self.events.len() == REPLAY_CAPACITY {
+            if let Some(id) = self.events.pop_front().and_then(|e| e.id) {
+                self.evicted = id;
+            }
+        }
+        self.events.push_back(event);
+        self.updated_at = Instant::now();
+    }
+}
+
+/// Keep the event in the replay log of the user if it could be replayed
+pub(crate) fn log_event(state: &AppState, user_id: u64, event: &EventEnvelope) {
+    if event.id.is_some() {
+        state
+            .replay
+            .entry(user_id)
+            .or_insert_with(ReplayLog::new)
+            .push(event.clone());
+    }
+}
+
+/// Notifications may have been missed, so the logs can't fill the gaps anymore.
+/// Connected clients are told to resync, and so are the clients resuming from an earlier event.
+pub(crate) fn resync_all(state: &AppState) {
+    let last_id = state.event_id.load(Ordering::SeqCst);
+    state.replay_floor.fetch_max(last_id, Ordering::SeqCst);
+    for mut log in state.replay.iter_mut() {
+        log.events.clear();
+        log.evicted = last_id;
+    }
+
+    let event = EventEnvelope::resync();
+    for tx in state.users.iter() {
+        if let Ok(n) = tx.send(event.clone()) {
+            state.metrics.resyncs.fetch_add(n as _, Ordering::Relaxed);
+        }
+    }
+}
+
+impl EventCursor {
+    /// Start after the last event seen by the client, or after the latest event if not known
+    pub(crate) fn new(state: &AppState, user_id: u64, last_id: Option<u64>) -> Self {
+        let last_id = last_id.unwrap_or_else(|| state.event_id.load(Ordering::SeqCst));
+        Self {
+            state: state.clone(),
+            user_id,
+            last_id,
+        }
+    }
+
+    /// Pass through an event received from the channel unless the client already has it
+    pub(crate) fn next(&mut self, event: EventEnvelope) -> Option<EventEnvelope> {
+        match event.id {
+            Some(id) if id <= self.last_id => None,
+            Some(id) => {
+                self.last_id = id;
+                Some(event)
+            }
+            None => Some(event),
+        }
+    }
+
+    /// The channel dropped events as the client fell behind, fill the gap from the replay log
+    pub(crate) fn lagged(&mut self, n: u64) -> Vec<EventEnvelope> {
+        warn!("user {} lagged behind {} events", self.user_id, n);
+        self.state
+            .metrics
+            .events_lagged
+            .fetch_add(n, Ordering::Relaxed);
+        self.catch_up()
+    }
+
+    /// Tell the client to reload its state, and continue from the latest event
+    pub(crate) fn resync(&mut self) -> Vec<EventEnvelope> {
+        self.state.metrics.resyncs.fetch_add(1, Ordering::Relaxed);
+        self.last_id = self.state.event_id.load(Ordering::SeqCst);
+        vec![EventEnvelope::resync()]
+    }
+
+    /// Events missed since the last one sent, or a Resync event if the id is out of the
+    /// window retained by the replay log. No log means the user had no events in the window.
+    pub(crate) fn catch_up(&mut self) -> Vec<EventEnvelope> {
+        let current = self.state.event_id.load(Ordering::SeqCst);
+        if self.last_id > current {
+            return self.resync();
+        }
+        if self.last_id == current {
+            return vec![];
+        }
+        let floor = self.state.replay_floor.load(Ordering::SeqCst);
+        let events = match self.state.replay.get(&self.user_id) {
+            Some(log) if log.evicted.max(floor) <= self.last_id => Some(
+                log.events
+                    .iter()
+                    .filter(|e| e.id > Some(self.last_id))
+                    .cloned()
+                    .collect::<Vec<_>>(),
+            ),
+            Some(_) => None,
+            None if floor <= self.last_id => Some(vec![]),
+            None => None,
+        };
+        let Some(events) = events else {
+            return self.resync();
+        };
+        if let Some(id) = events.last().and_then(|e| e.id) {
+            self.last_id = id;
+        }
+        events
+    }
+}
@@ -0,0 +1,93 @@
+use crate::AppState;
+use chat_core::{TokenId, TokenRevoked, User};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// SSE and websocket streams of an access token, closed once the token is revoked or the user
+/// is deactivated in the workspace of the token
+pub struct Session {
+    user_id: i64,
+    ws_id: i64,
+    token: TokenId,
+    streams: usize,
+    cancel: CancellationToken,
+}
+
+/// Dropped with a stream of the token, the session is gone with its last stream
+pub(crate) struct SessionGuard {
+    state: AppState,
+    jti: String,
+}
+
+/// Open a stream of the token, the returned token is cancelled once the token is revoked
+pub(crate) fn session_opened(
+    state: &AppState,
+    user: &User,
+    token: &TokenId,
+) -> (CancellationToken, SessionGuard) {
+    let user_id = user.id;
+    let cancel = {
+        let mut session = state
+            .sessions
+            .entry(token.jti.clone())
+            .or_insert_with(|| Session {
+                user_id,
+                ws_id: user.ws_id,
+                token: token.clone(),
+                streams: 0,
+                cancel: CancellationToken::new(),
+            });
+        session.streams += 1;
+        session.cancel.clone()
+    };
+    // revoked or deactivated after the token was verified
+    if state.revocations.is_revoked(user_id, token)
+        || state.deactivated.is_deactivated(user.ws_id, user_id)
+    {
+        cancel.cancel();
+    }
+    let guard = SessionGuard {
+        state: state.clone(),
+        jti: token.jti.clone(),
+    };
+    (cancel, guard)
+}
+
+/// Reject the revoked tokens from now on, and close the streams using them
+pub(crate) fn token_revoked(state: &AppState, revoked: &TokenRevoked) {
+    state.revocations.apply(revoked);
+    state.sessions.retain(|_, session| {
+        if revoked.revokes(session.user_id, &session.token) {
+            session.cancel.cancel();
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// Close the streams of the user with tokens of the workspace, its streams with tokens of other
+/// workspaces stay. Clients reconnect with their tokens, the ones of the workspace are rejected.
+pub(crate) fn member_deactivated(state: &AppState, ws_id: i64, user_id: i64) {
+    state.sessions.retain(|_, session| {
+        if session.user_id == user_id && session.ws_id == ws_id {
+            info!(
+                "User {} deactivated in workspace {}, closing its streams",
+                user_id, ws_id
+            );
+            session.cancel.cancel();
+            false
+        } else {
+            true
+        }
+    });
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.state.sessions.remove_if_mut(&self.jti, |_, session| {
+            session.streams -= 1;
+            session.streams == 0
+        });
+    }
+}
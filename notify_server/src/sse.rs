@@ -1,43 +1,70 @@
-use crate::{AppEvent, AppState};
+use crate::{
+    presence::{user_connected, PresenceGuard},
+    replay::{format_event_id, parse_event_id, EventCursor, EventEnvelope},
+    session::session_opened,
+    AppEvent, AppState,
+};
 use axum::{
-    extract::State,
+    extract::{Query, State},
+    http::HeaderMap,
     response::{sse::Event, Sse},
     Extension,
 };
-use chat_core::User;
-use futures::Stream;
+use chat_core::{TokenId, User};
+use futures::{future, stream, Stream, StreamExt};
+use serde::Deserialize;
 use std::{convert::Infallible, time::Duration};
 use tokio::sync::broadcast;
-use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 use tracing::debug;
 
 const CHANNEL_CAPACITY: usize = 256;
 
+#[derive(Debug, Deserialize)]
+pub(crate) struct ResumeParams {
+    /// Same as the `Last-Event-ID` header, for clients that can't set headers
+    #[serde(default)]
+    last_event_id: Option<String>,
+}
+
 pub(crate) async fn sse_handler(
     Extension(user): Extension<User>,
+    Extension(token): Extension<TokenId>,
     State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<ResumeParams>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let user_id = user.id as u64;
-    let users = &state.users;
-
-    let rx = if let Some(tx) = users.get(&user_id) {
-        tx.subscribe()
-    } else {
-        let (tx, rx) = broadcast::channel(CHANNEL_CAPACITY);
-        state.users.insert(user_id, tx);
-        rx
-    };
+    let last_event_id = last_event_id(&headers, &params);
+    let (rx, mut cursor, backlog, guard) = subscribe(&state, &user, last_event_id.as_deref()).await;
+    let (cancel, session) = session_opened(&state, &user, &token);
+    let ws_id = user.ws_id as u64;
 
-    let stream = BroadcastStream::new(rx).filter_map(|v| v.ok()).map(|v| {
-        let name = match v.as_ref() {
-            AppEvent::NewChat(_) => "NewChat",
-            AppEvent::AddToChat(_) => "AddToChat",
-            AppEvent::RemoveFromChat(_) => "RemoveFromChat",
-            AppEvent::NewMessage(_) => "NewMessage",
+    let live = BroadcastStream::new(rx).flat_map(move |v| {
+        // the guards live as long as the stream
+        let _guard = &guard;
+        let _session = &session;
+        let events = match v {
+            Ok(event) => cursor.next(event).into_iter().collect(),
+            Err(BroadcastStreamRecvError::Lagged(n)) => cursor.lagged(n),
         };
-        let v = serde_json::to_string(&v).expect("failed to serialize event");
-        debug!("Sending event {}: {:?}", name, v);
-        Ok(Event::default().data(v).event(name))
+        stream::iter(events)
+    });
+
+    // the stream ends once the token is revoked
+    let live = live.take_until(cancel.cancelled_owned());
+    // events of other workspaces of the user are left to the streams of their tokens
+    let stream = stream::iter(backlog)
+        .chain(live)
+        .filter(move |v| future::ready(v.visible_in(ws_id)));
+    let stream = stream.map(move |v| {
+        let name = event_name(&v.event);
+        let data = serde_json::to_string(&v.event).expect("failed to serialize event");
+        debug!("Sending event {}: {:?}", name, data);
+        let event = Event::default().data(data).event(name);
+        Ok(match v.id {
+            Some(id) => event.id(format_event_id(&state, id)),
+            None => event,
+        })
     });
 
     Sse::new(stream).keep_alive(
@@ -46,3 +73,63 @@ pub(crate) async fn sse_handler(
             .text("Keep-alive"),
     )
 }
+
+/// Subscribe to the events of the user, starting after the last event received by the client.
+/// Missed events are returned to be sent first, the user is connected as long as the guard is alive
+pub(crate) async fn subscribe(
+    state: &AppState,
+    user: &User,
+    last_event_id: Option<&str>,
+) -> (
+    broadcast::Receiver<EventEnvelope>,
+    EventCursor,
+    Vec<EventEnvelope>,
+    PresenceGuard,
+) {
+    let user_id = user.id as u64;
+    // an id from another instance or an older run can't be resumed from
+    let last_id = last_event_id.map(|v| parse_event_id(state, v));
+    // the cursor starts before subscribing, so no event falls in between
+    let mut cursor = EventCursor::new(state, user_id, last_id.flatten());
+    let rx = state
+        .users
+        .entry(user_id)
+        .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .subscribe();
+    let backlog = match last_id {
+        Some(Some(_)) => cursor.catch_up(),
+        Some(None) => cursor.resync(),
+        None => vec![],
+    };
+    let guard = user_connected(state, user_id, user.ws_id as _).await;
+    (rx, cursor, backlog, guard)
+}
+
+/// Last event received by the client, from the `Last-Event-ID` header or the `last_event_id` query
+pub(crate) fn last_event_id(headers: &HeaderMap, params: &ResumeParams) -> Option<String> {
+    headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+        .or_else(|| params.last_event_id.clone())
+}
+
+fn event_name(event: &AppEvent) -> &'static str {
+    match event {
+        AppEvent::NewChat(_) => "NewChat",
+        AppEvent::AddToChat(_) => "AddToChat",
+        AppEvent::RemoveFromChat(_) => "RemoveFromChat",
+        AppEvent::ChatUpdated(_) => "ChatUpdated",
+        AppEvent::NewMessage(_) => "NewMessage",
+        AppEvent::NewThreadReply(_) => "NewThreadReply",
+        AppEvent::MessageUpdated(_) => "MessageUpdated",
+        AppEvent::MessageDeleted(_) => "MessageDeleted",
+        AppEvent::ReactionAdded(_) => "ReactionAdded",
+        AppEvent::ReactionRemoved(_) => "ReactionRemoved",
+        AppEvent::ReadReceipt(_) => "ReadReceipt",
+        AppEvent::Typing(_) => "Typing",
+        AppEvent::TypingStopped(_) => "TypingStopped",
+        AppEvent::PresenceChanged(_) => "PresenceChanged",
+        AppEvent::Resync => "Resync",
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{
+    notif::{is_chat_member, publish_typing},
+    presence::user_active,
+    replay::{format_event_id, EventEnvelope},
+    session::session_opened,
+    sse::{last_event_id, subscribe, ResumeParams},
+    AppEvent, AppState,
+};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Query, State, WebSocketUpgrade,
+    },
+    http::HeaderMap,
+    response::IntoResponse,
+    Extension,
+};
+use chat_core::{mark_chat_read, TokenId, User};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+
+/// Commands sent by the client over the websocket
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum WsCommand {
+    /// Only receive activity events of these chats, all chats if empty
+    Subscribe {
+        chat_ids: Vec<u64>,
+    },
+    Typing {
+        chat_id: u64,
+    },
+    /// Mark the chat read up to the message
+    Ack {
+        chat_id: u64,
+        message_id: u64,
+    },
+    Ping,
+}
+
+/// An event frame, the same AppEvent JSON with the id of the event if it has one
+#[derive(Debug, Serialize)]
+struct WsEvent<'a> {
+    #[serde(rename = "eventId", skip_serializing_if = "Option::is_none")]
+    event_id: Option<String>,
+    #[serde(flatten)]
+    event: &'a AppEvent,
+}
+
+/// Replies to the client commands, sent along with the AppEvent frames
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event")]
+pub enum WsReply {
+    Pong,
+    Error { error: String },
+}
+
+impl WsReply {
+    fn error(e: impl ToString) -> Self {
+        Self::Error {
+            error: e.to_string(),
+        }
+    }
+}
+
+pub(crate) async fn ws_handler(
+    Extension(user): Extension<User>,
+    Extension(token): Extension<TokenId>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<ResumeParams>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let last_event_id = last_event_id(&headers, &params);
+    ws.on_upgrade(move |socket| handle_socket(socket, state, user, token, last_event_id))
+}
+
+async fn handle_socket(
+    mut socket: WebSocket,
+    state: AppState,
+    user: User,
+    token: TokenId,
+    last_event_id: Option<String>,
+) {
+    let user_id = user.id as u64;
+    let ws_id = user.ws_id as u64;
+    let (mut rx, mut cursor, backlog, _guard) =
+        subscribe(&state, &user, last_event_id.as_deref()).await;
+    let (cancel, _session) = session_opened(&state, &user, &token);
+    let mut chat_ids = HashSet::new();
+
+    if !send_events(&state, &mut socket, backlog, ws_id, &chat_ids).await {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            // the socket is closed once the token is revoked
+            _ = cancel.cancelled() => break,
+            event = rx.recv() => {
+                let events = match event {
+                    Ok(event) => cursor.next(event).into_iter().collect(),
+                    Err(RecvError::Lagged(n)) => cursor.lagged(n),
+                    Err(RecvError::Closed) => break,
+                };
+                if !send_events(&state, &mut socket, events, ws_id, &chat_ids).await {
+                    break;
+                }
+            }
+            msg = socket.recv() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    // ping frames are answered by axum
+                    Some(Ok(_)) => continue,
+                };
+                user_active(&state, user_id).await;
+                let reply = match serde_json::from_str(&text) {
+                    Ok(cmd) => handle_command(&state, &user, cmd, &mut chat_ids).await,
+                    Err(e) => Some(WsReply::error(format!("invalid command: {}", e))),
+                };
+                if let Some(reply) = reply {
+                    let v = serde_json::to_string(&reply).expect("failed to serialize reply");
+                    if socket.send(Message::Text(v)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Send the events with their ids, return false if the socket is gone
+async fn send_events(
+    state: &AppState,
+    socket: &mut WebSocket,
+    events: Vec<EventEnvelope>,
+    ws_id: u64,
+    chat_ids: &HashSet<u64>,
+) -> bool {
+    for event in events {
+        if !event.visible_in(ws_id) {
+            continue;
+        }
+        if let Some(chat_id) = event.event.chat_id() {
+            if !chat_ids.is_empty() && !chat_ids.contains(&chat_id) {
+                continue;
+            }
+        }
+        let frame = WsEvent {
+            event_id: event.id.map(|id| format_event_id(state, id)),
+            event: &event.event,
+        };
+        let v = serde_json::to_string(&frame).expect("failed to serialize event");
+        debug!("Sending event: {:?}", v);
+        if socket.send(Message::Text(v)).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+async fn handle_command(
+    state: &AppState,
+    user: &User,
+    cmd: WsCommand,
+    chat_ids: &mut HashSet<u64>,
+) -> Option<WsReply> {
+    match cmd {
+        WsCommand::Subscribe { chat_ids: ids } => {
+            *chat_ids = ids.into_iter().collect();
+            None
+        }
+        WsCommand::Ping => Some(WsReply::Pong),
+        WsCommand::Typing { chat_id } => match publish_typing(state, chat_id, user).await {
+            Ok(true) => None,
+            Ok(false) => Some(WsReply::error(format!("chat {} not found", chat_id))),
+            Err(e) => Some(WsReply::error(e)),
+        },
+        WsCommand::Ack {
+            chat_id,
+            message_id,
+        } => match ack(state, chat_id, message_id, user).await {
+            Ok(true) => None,
+            Ok(false) => Some(WsReply::error(format!(
+                "message {} not found in chat {}",
+                message_id, chat_id
+            ))),
+            Err(e) => Some(WsReply::error(e)),
+        },
+    }
+}
+
+/// Move the read position forward, return false if the message isn't in a chat of the user
+async fn ack(state: &AppState, chat_id: u64, message_id: u64, user: &User) -> anyhow::Result<bool> {
+    // chats of other workspaces are hidden until the user switches to them
+    if !is_chat_member(state, chat_id, user).await? {
+        return Ok(false);
+    }
+    let read = mark_chat_read(&state.pool, chat_id as _, user.id, message_id as _).await?;
+    Ok(read.is_some())
+}